@@ -0,0 +1,113 @@
+use bevy::math::{vec2, Quat, Vec2};
+use bevy::transform::components::Transform;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use platformer::collision::{Collider, Layers, Obb, Shape};
+use platformer::spatial_hash::SpatialHash;
+
+const CELL_SIZE: f32 = 64f32;
+const CARS: usize = 32;
+const CAR_LENGTH: f32 = 36f32;
+const CAR_WIDTH: f32 = 18f32;
+
+struct Body {
+    collider: Collider,
+    transform: Transform,
+}
+
+fn at(position: Vec2, angle: f32) -> Transform {
+    Transform { translation: position.extend(0f32), rotation: Quat::from_rotation_z(angle), ..Default::default() }
+}
+
+/// Walls around a circle, like the edges of a closed road: boxes and fence polylines in turn,
+/// followed by cars along the wall, half of them touching it.
+fn scene(walls: usize) -> Vec<Body> {
+    let radius = walls as f32 * 2f32;
+    let angle = |index: f32| index / walls as f32 * std::f32::consts::TAU;
+    let point = |angle: f32, radius: f32| vec2(angle.cos(), angle.sin()) * radius;
+    let length = radius * angle(1f32);
+    let mut bodies: Vec<Body> = (0..walls)
+        .map(|index| {
+            let middle = angle(index as f32 + 0.5f32);
+            let shape = if index % 2 == 0 {
+                Shape::Box { size: vec2(10f32, length) }
+            } else {
+                Shape::Polyline { points: vec![vec2(0f32, -length / 2f32), vec2(0f32, length / 2f32)] }
+            };
+            Body {
+                collider: Collider::solid(shape, Layers::WALLS),
+                transform: at(point(middle, radius), middle),
+            }
+        })
+        .collect();
+    let step = (walls / CARS).max(1);
+    bodies.extend((0..walls).step_by(step).map(|index| {
+        let middle = angle(index as f32 + 0.5f32);
+        let gap = if index % 2 == 0 { 10f32 } else { 20f32 };
+        Body {
+            collider: Collider::solid(Shape::Box { size: vec2(CAR_LENGTH, CAR_WIDTH) }, Layers::CARS),
+            transform: at(point(middle, radius - gap), middle + std::f32::consts::FRAC_PI_2),
+        }
+    }));
+    bodies
+}
+
+fn car_boxes(bodies: &[Body]) -> Vec<(usize, Obb)> {
+    bodies.iter()
+        .enumerate()
+        .filter(|(_, body)| body.collider.layers == Layers::CARS)
+        .map(|(car, body)| (car, Obb::from_transform(&body.transform, vec2(CAR_LENGTH, CAR_WIDTH))))
+        .collect()
+}
+
+fn contacts<'a>(bodies: &'a [Body], car: usize, obb: &'a Obb, others: impl IntoIterator<Item = usize> + 'a)
+    -> impl Iterator<Item = usize> + 'a
+{
+    others.into_iter()
+        .filter(move |other| *other != car && bodies[car].collider.interacts(&bodies[*other].collider))
+        .filter(move |other| bodies[*other].collider.shape.collide_box(obb, &bodies[*other].transform).is_some())
+}
+
+fn brute_force(bodies: &[Body], cars: &[(usize, Obb)]) -> usize {
+    cars.iter()
+        .map(|(car, obb)| contacts(bodies, *car, obb, 0..bodies.len()).count())
+        .sum()
+}
+
+/// Same as the collision index, every collider is inserted again on every tick.
+fn build(bodies: &[Body]) -> SpatialHash<usize> {
+    let mut index = SpatialHash::new(CELL_SIZE);
+    for (body, Body { collider, transform }) in bodies.iter().enumerate() {
+        index.insert(body, collider.shape.aabb(transform));
+    }
+    index
+}
+
+fn query(index: &SpatialHash<usize>, bodies: &[Body], cars: &[(usize, Obb)]) -> usize {
+    cars.iter()
+        .map(|(car, obb)| contacts(bodies, *car, obb, index.query(obb.aabb())).count())
+        .sum()
+}
+
+fn broad_phase(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("broad_phase");
+    for count in [100usize, 1_000, 10_000].iter() {
+        let bodies = scene(*count);
+        let cars = car_boxes(&bodies);
+        let index = build(&bodies);
+        assert!(brute_force(&bodies, &cars) > 0);
+        assert_eq!(brute_force(&bodies, &cars), query(&index, &bodies, &cars));
+        group.bench_with_input(BenchmarkId::new("brute_force", count), count, |bench, _| {
+            bench.iter(|| brute_force(black_box(&bodies), black_box(&cars)))
+        });
+        group.bench_with_input(BenchmarkId::new("spatial_hash_query", count), count, |bench, _| {
+            bench.iter(|| query(black_box(&index), black_box(&bodies), black_box(&cars)))
+        });
+        group.bench_with_input(BenchmarkId::new("spatial_hash_rebuild", count), count, |bench, _| {
+            bench.iter(|| query(&build(black_box(&bodies)), black_box(&bodies), black_box(&cars)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, broad_phase);
+criterion_main!(benches);
@@ -0,0 +1,433 @@
+use std::collections::HashMap;
+use std::fmt;
+use bevy::{
+    app::{AppBuilder, EventReader, Plugin},
+    asset::{AddAsset, AssetEvent, AssetLoader, Assets, LoadContext, LoadedAsset},
+    ecs::{
+        entity::Entity,
+        query::{With, Without},
+        schedule::SystemLabel,
+        system::{Commands, IntoSystem, Query, Res, ResMut},
+    },
+    input::{
+        gamepad::{
+            Gamepad, GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType, GamepadEvent,
+            GamepadEventType,
+        },
+        keyboard::KeyCode,
+        Axis,
+        Input,
+    },
+    log::{error, info},
+    prelude::ParallelSystemDescriptorCoercion,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use serde::{Deserialize, Serialize};
+use crate::damage::Eliminated;
+use crate::resources::GameResources;
+use crate::respawn::Respawn;
+use crate::vehicle::CarControls;
+
+/// Values at or above it count as a pressed action.
+const PRESSED: f32 = 0.5;
+
+/// Players sharing the screen.
+pub const MAX_PLAYERS: usize = 4;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+pub enum ActionsPluginSystem {
+    Update,
+    Controls,
+}
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_asset::<InputConfig>()
+            .init_asset_loader::<InputConfigLoader>()
+            .init_resource::<Bindings>()
+            .init_resource::<Gamepads>()
+            .add_system(gamepad_events.system().before(ActionsPluginSystem::Update))
+            .add_system(input_config_events.system().before(ActionsPluginSystem::Update))
+            .add_system(update_actions.system().label(ActionsPluginSystem::Update))
+            .add_system(
+                player_controls.system()
+                    .label(ActionsPluginSystem::Controls)
+                    .after(ActionsPluginSystem::Update));
+    }
+}
+
+/// What a player asks of a car, whatever device it comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputAction {
+    Accelerate,
+    Brake,
+    SteerLeft,
+    SteerRight,
+    Reset,
+    Handbrake,
+}
+
+impl InputAction {
+    pub const ALL: [InputAction; 6] = [
+        InputAction::Accelerate,
+        InputAction::Brake,
+        InputAction::SteerLeft,
+        InputAction::SteerRight,
+        InputAction::Reset,
+        InputAction::Handbrake,
+    ];
+}
+
+/// Source of an action value from 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    /// Half of a gamepad axis, `positive` picks the side.
+    Axis { axis: GamepadAxisType, positive: bool },
+    /// Gamepad button, triggers are analog.
+    Button(GamepadButtonType),
+}
+
+/// Maps raw analog values: inside `deadzone` they are 0,
+/// the rest is stretched to 0..1 and raised to `exponent` for finer control near the center.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub deadzone: f32,
+    pub exponent: f32,
+}
+
+impl Response {
+    pub fn apply(&self, value: f32) -> f32 {
+        if value <= self.deadzone || self.deadzone >= 1f32 {
+            return 0f32;
+        }
+        ((value - self.deadzone) / (1f32 - self.deadzone)).min(1f32).powf(self.exponent)
+    }
+}
+
+/// Responses of sticks and of buttons, the latter are mostly triggers.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AnalogSettings {
+    pub stick: Response,
+    pub trigger: Response,
+}
+
+impl Default for AnalogSettings {
+    fn default() -> Self {
+        AnalogSettings {
+            stick: Response { deadzone: 0.15, exponent: 1.5 },
+            trigger: Response { deadzone: 0.05, exponent: 1f32 },
+        }
+    }
+}
+
+/// Bindings of every action, an action may have several of them.
+pub type Layout = HashMap<InputAction, Vec<Binding>>;
+
+/// Input file layout, every one of `players` picks one of `layouts` by its name.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "7ea9d287-d1a6-4c0c-af42-b53b0cf6ad12"]
+pub struct InputConfig {
+    pub players: Vec<PlayerInput>,
+    pub layouts: HashMap<String, Layout>,
+    #[serde(default)]
+    pub analog: AnalogSettings,
+}
+
+/// `gamepad` is the index among connected gamepads, players without one use keys only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerInput {
+    pub layout: String,
+    #[serde(default)]
+    pub gamepad: Option<usize>,
+}
+
+#[derive(Debug)]
+pub enum InputConfigError {
+    Parse(ron::Error),
+    NoPlayers,
+    TooManyPlayers { count: usize },
+    UnknownLayout { player: usize, layout: String },
+}
+
+impl fmt::Display for InputConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputConfigError::Parse(error) => write!(f, "Failed to parse input config at {}", error),
+            InputConfigError::NoPlayers => write!(f, "Input config has no players"),
+            InputConfigError::TooManyPlayers { count } =>
+                write!(f, "Input config has {} players, at most {} are supported", count, MAX_PLAYERS),
+            InputConfigError::UnknownLayout { player, layout } =>
+                write!(f, "Player {} has unknown layout {:?}", player, layout),
+        }
+    }
+}
+
+impl std::error::Error for InputConfigError {}
+
+impl From<ron::Error> for InputConfigError {
+    fn from(error: ron::Error) -> Self {
+        InputConfigError::Parse(error)
+    }
+}
+
+impl InputConfig {
+    pub fn from_ron(source: &str) -> Result<Self, InputConfigError> {
+        let config: InputConfig = ron::de::from_str(source)?;
+        config.player_bindings()?;
+        Ok(config)
+    }
+
+    pub fn player_bindings(&self) -> Result<Vec<PlayerBindings>, InputConfigError> {
+        if self.players.is_empty() {
+            return Err(InputConfigError::NoPlayers);
+        }
+        if self.players.len() > MAX_PLAYERS {
+            return Err(InputConfigError::TooManyPlayers { count: self.players.len() });
+        }
+        self.players.iter()
+            .enumerate()
+            .map(|(player, input)| match self.layouts.get(&input.layout) {
+                Some(layout) => Ok(PlayerBindings { layout: layout.clone(), gamepad: input.gamepad }),
+                None => Err(InputConfigError::UnknownLayout { player, layout: input.layout.clone() }),
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct InputConfigLoader;
+
+impl AssetLoader for InputConfigLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let source = std::str::from_utf8(bytes)?;
+            let config = InputConfig::from_ron(source)?;
+            load_context.set_default_asset(LoadedAsset::new(config));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["input.ron"]
+    }
+}
+
+/// Marks a car driven by a local player, `0` is the first one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Player(pub usize);
+
+#[derive(Debug, Clone)]
+pub struct PlayerBindings {
+    pub layout: Layout,
+    pub gamepad: Option<usize>,
+}
+
+/// Bindings of every player, a single one on arrow keys until the input config is loaded.
+pub struct Bindings {
+    pub players: Vec<PlayerBindings>,
+    pub analog: AnalogSettings,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let layout = [
+            (InputAction::Accelerate, KeyCode::Up),
+            (InputAction::Brake, KeyCode::Down),
+            (InputAction::SteerLeft, KeyCode::Left),
+            (InputAction::SteerRight, KeyCode::Right),
+            (InputAction::Reset, KeyCode::Back),
+            (InputAction::Handbrake, KeyCode::Space),
+        ];
+        let player = PlayerBindings {
+            layout: layout.iter().map(|(action, key)| (*action, vec![Binding::Key(*key)])).collect(),
+            gamepad: Some(0),
+        };
+        Bindings { players: vec![player], analog: AnalogSettings::default() }
+    }
+}
+
+/// Value of every action of a player from 0 to 1 in this frame and in the previous one.
+#[derive(Debug, Default)]
+pub struct ActionState {
+    current: HashMap<InputAction, f32>,
+    previous: HashMap<InputAction, f32>,
+}
+
+impl ActionState {
+    pub fn value(&self, action: InputAction) -> f32 {
+        self.current.get(&action).copied().unwrap_or(0f32)
+    }
+
+    pub fn pressed(&self, action: InputAction) -> bool {
+        self.value(action) >= PRESSED
+    }
+
+    pub fn just_pressed(&self, action: InputAction) -> bool {
+        let previous = self.previous.get(&action).copied().unwrap_or(0f32);
+        self.pressed(action) && previous < PRESSED
+    }
+
+    /// Axis from -1 to 1 made of two opposite actions.
+    pub fn axis(&self, negative: InputAction, positive: InputAction) -> f32 {
+        self.value(positive) - self.value(negative)
+    }
+}
+
+fn input_config_events(
+    mut events: EventReader<AssetEvent<InputConfig>>,
+    configs: Res<Assets<InputConfig>>,
+    game_resources: Res<GameResources>,
+    mut bindings: ResMut<Bindings>,
+) {
+    for event in events.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != game_resources.input {
+            continue;
+        }
+        let config = match configs.get(handle) {
+            Some(config) => config,
+            None => continue,
+        };
+        match config.player_bindings() {
+            Ok(players) => {
+                info!("Input config for {} players", players.len());
+                bindings.players = players;
+                bindings.analog = config.analog;
+            },
+            Err(error) => error!("{}", error),
+        }
+    }
+}
+
+/// Connected gamepads in the order they came.
+#[derive(Debug, Default)]
+pub struct Gamepads(pub Vec<Gamepad>);
+
+fn gamepad_events(mut events: EventReader<GamepadEvent>, mut gamepads: ResMut<Gamepads>) {
+    for GamepadEvent(gamepad, event) in events.iter() {
+        match event {
+            GamepadEventType::Connected => {
+                info!("Gamepad {:?} connected", gamepad);
+                gamepads.0.push(*gamepad);
+            },
+            GamepadEventType::Disconnected => {
+                info!("Gamepad {:?} disconnected", gamepad);
+                gamepads.0.retain(|connected| connected != gamepad);
+            },
+            _ => {},
+        }
+    }
+}
+
+fn update_actions(
+    keys: Res<Input<KeyCode>>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<Axis<GamepadButton>>,
+    gamepads: Res<Gamepads>,
+    bindings: Res<Bindings>,
+    mut players: Query<(&Player, &mut ActionState)>,
+) {
+    let analog = bindings.analog;
+    for (player, mut state) in players.iter_mut() {
+        let state = &mut *state;
+        std::mem::swap(&mut state.current, &mut state.previous);
+        state.current.clear();
+        let player = match bindings.players.get(player.0) {
+            Some(player) => player,
+            None => continue,
+        };
+        let gamepad = player.gamepad.and_then(|gamepad| gamepads.0.get(gamepad)).copied();
+        let value = |binding: &Binding| match (binding, gamepad) {
+            (Binding::Key(key), _) => if keys.pressed(*key) { 1f32 } else { 0f32 },
+            (Binding::Axis { axis, positive }, Some(gamepad)) => {
+                let value = axes.get(GamepadAxis(gamepad, *axis)).unwrap_or(0f32);
+                analog.stick.apply(if *positive { value } else { -value })
+            },
+            (Binding::Button(button), Some(gamepad)) =>
+                analog.trigger.apply(buttons.get(GamepadButton(gamepad, *button)).unwrap_or(0f32)),
+            (_, None) => 0f32,
+        };
+        for action in InputAction::ALL.iter() {
+            let value = player.layout.get(action)
+                .into_iter()
+                .flatten()
+                .map(value)
+                .fold(0f32, f32::max);
+            state.current.insert(*action, value);
+        }
+    }
+}
+
+/// Cars of players that can still drive.
+type Driving = (With<Player>, Without<Eliminated>);
+
+/// Players drive their cars through the same controls as any other driver, until the car is eliminated.
+fn player_controls(mut commands: Commands, mut cars: Query<(Entity, &ActionState, &mut CarControls), Driving>) {
+    for (car, actions, mut controls) in cars.iter_mut() {
+        if actions.just_pressed(InputAction::Reset) {
+            commands.entity(car).insert(Respawn);
+        }
+        controls.throttle = actions.value(InputAction::Accelerate);
+        controls.brake = actions.value(InputAction::Brake);
+        controls.steer = actions.axis(InputAction::SteerRight, InputAction::SteerLeft);
+        controls.handbrake = actions.pressed(InputAction::Handbrake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(layouts: &[&str]) -> InputConfig {
+        let mut config = InputConfig::from_ron(include_str!("../assets/input/default.input.ron")).unwrap();
+        config.players = layouts.iter()
+            .map(|layout| PlayerInput { layout: layout.to_string(), gamepad: None })
+            .collect();
+        config
+    }
+
+    #[test]
+    fn response_is_zero_inside_the_deadzone_and_curved_outside() {
+        let response = Response { deadzone: 0.2, exponent: 2f32 };
+        assert_eq!(response.apply(0f32), 0f32);
+        assert_eq!(response.apply(0.2), 0f32);
+        assert!((response.apply(0.6) - 0.25).abs() < 1e-6);
+        assert_eq!(response.apply(1f32), 1f32);
+        assert_eq!(response.apply(1.5), 1f32);
+        let linear = Response { deadzone: 0f32, exponent: 1f32 };
+        assert_eq!(linear.apply(0.5), 0.5);
+        // a deadzone covering everything never fires.
+        assert_eq!(Response { deadzone: 1f32, exponent: 1f32 }.apply(1f32), 0f32);
+    }
+
+    #[test]
+    fn player_bindings_pick_the_layout_of_each_player() {
+        let bindings = config(&["arrows", "wasd"]).player_bindings().unwrap();
+        assert_eq!(bindings.len(), 2);
+        assert_eq!(bindings[1].layout[&InputAction::Reset][0], Binding::Key(KeyCode::R));
+    }
+
+    #[test]
+    fn player_bindings_reject_invalid_players() {
+        assert!(matches!(config(&[]).player_bindings(), Err(InputConfigError::NoPlayers)));
+        let crowd = vec!["arrows"; MAX_PLAYERS + 1];
+        assert!(matches!(
+            config(&crowd).player_bindings(),
+            Err(InputConfigError::TooManyPlayers { count }) if count == MAX_PLAYERS + 1));
+        assert!(matches!(
+            config(&["arrows", "dvorak"]).player_bindings(),
+            Err(InputConfigError::UnknownLayout { player: 1, layout }) if layout == "dvorak"));
+    }
+}
@@ -0,0 +1,508 @@
+use bevy::{
+    app::{AppBuilder, Plugin},
+    ecs::{
+        entity::Entity,
+        query::{Added, With, Without},
+        schedule::SystemLabel,
+        system::{Commands, IntoSystem, Query},
+    },
+    math::{Vec2, Vec3},
+    prelude::ParallelSystemDescriptorCoercion,
+};
+use serde::{Deserialize, Serialize};
+use crate::damage::Eliminated;
+use crate::race::RacePluginSystem;
+use crate::respawn::Respawn;
+use crate::road::Width;
+use crate::simulation::{Simulated, SimulationStage, SimulationSystem, TIMESTEP};
+use crate::track::Centerline;
+use crate::vehicle::{CarControls, Vehicle, VehiclePluginSystem, VehicleState, STOP_SPEED};
+
+/// Distance between the points of a racing line.
+const LINE_SPACING: f32 = 10f32;
+/// Passes pulling the racing line straight between the road borders.
+const SMOOTHING_PASSES: usize = 200;
+/// Room kept between the racing line and the road borders, about half a car.
+const BORDER_MARGIN: f32 = 12f32;
+/// Sideways acceleration a car holds in corners, in pixels per second squared.
+const LATERAL_ACCELERATION: f32 = 700f32;
+/// Deceleration planned before corners.
+const BRAKING: f32 = 450f32;
+const TOP_SPEED: f32 = 550f32;
+/// Shortest distance to the pure pursuit target, the rest grows with speed.
+const MIN_LOOKAHEAD: f32 = 30f32;
+/// Speed errors larger than that use the full throttle or brake.
+const SPEED_BAND: f32 = 50f32;
+/// Cars closer than that in front are avoided.
+const AVOID_DISTANCE: f32 = 120f32;
+/// Cars further sideways than that are not in the way.
+const AVOID_WIDTH: f32 = 30f32;
+/// How fast drivers move across the racing line, in pixels per second.
+const OFFSET_SPEED: f32 = 60f32;
+/// Points searched for the nearest one from the last known point.
+const SEARCH_WINDOW: usize = 60;
+/// Cars further than that from the nearest point of the window were teleported,
+/// the whole line is searched then.
+const RESEARCH_DISTANCE: f32 = 120f32;
+/// Drivers going slower than that while they want to go faster are stuck.
+const STUCK_SPEED: f32 = 10f32;
+/// Seconds stuck before backing off whatever holds the car.
+const STUCK_TIME: f32 = 1.5;
+/// Seconds spent backing off.
+const REVERSE_TIME: f32 = 1f32;
+/// Seconds stuck before giving up and respawning, backing off did not help by then.
+const RESPAWN_TIME: f32 = 4f32;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+pub enum AiPluginSystem {
+    RacingLine,
+    Drive,
+}
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system(racing_line.system().label(AiPluginSystem::RacingLine))
+            .add_system_to_stage(
+                SimulationStage,
+                drive.system()
+                    .label(AiPluginSystem::Drive)
+                    .after(SimulationSystem::Begin)
+                    .after(RacePluginSystem::LineUp)
+                    .before(VehiclePluginSystem::Physics));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+/// How well an AI drives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AiSkill {
+    /// Part of the planned speed the driver goes at.
+    pub pace: f32,
+    /// Seconds of driving to the pure pursuit target, longer ones cut corners less precisely.
+    pub lookahead: f32,
+    /// Part of the room beside the racing line used to overtake, 0 only follows.
+    pub aggression: f32,
+}
+
+impl Difficulty {
+    pub fn skill(self) -> AiSkill {
+        match self {
+            Difficulty::Easy => AiSkill { pace: 0.75, lookahead: 0.45, aggression: 0f32 },
+            Difficulty::Medium => AiSkill { pace: 0.9, lookahead: 0.35, aggression: 0.5 },
+            Difficulty::Hard => AiSkill { pace: 1f32, lookahead: 0.3, aggression: 1f32 },
+        }
+    }
+}
+
+/// Drives a car along the racing line through its `CarControls`.
+#[derive(Debug, Clone)]
+pub struct AiDriver {
+    pub skill: AiSkill,
+    /// Nearest point of the racing line on the last tick.
+    index: Option<usize>,
+    /// Distance from the racing line to the left, moved while overtaking.
+    offset: f32,
+    /// Seconds since the car last moved forward while the driver wanted it to.
+    stuck: f32,
+}
+
+/// What a driver does about being stuck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Recovery {
+    Drive,
+    Reverse,
+    Respawn,
+}
+
+impl AiDriver {
+    pub fn new(difficulty: Difficulty) -> Self {
+        AiDriver { skill: difficulty.skill(), index: None, offset: 0f32, stuck: 0f32 }
+    }
+
+    /// Counts the time stuck for one tick, only moving forward resets it.
+    fn recover(&mut self, forward_speed: f32, target_speed: f32) -> Recovery {
+        if forward_speed >= STUCK_SPEED || target_speed < STUCK_SPEED {
+            self.stuck = 0f32;
+            return Recovery::Drive;
+        }
+        self.stuck += TIMESTEP as f32;
+        if self.stuck >= RESPAWN_TIME {
+            self.stuck = 0f32;
+            Recovery::Respawn
+        } else if (STUCK_TIME..STUCK_TIME + REVERSE_TIME).contains(&self.stuck) {
+            Recovery::Reverse
+        } else {
+            Recovery::Drive
+        }
+    }
+}
+
+/// Smooth line through the road with the speed to take at every point,
+/// it lives next to `Centerline`. `room` is the distance to the road borders.
+#[derive(Debug, Clone)]
+pub struct RacingLine {
+    pub points: Vec<Vec2>,
+    /// Unit vectors to the left of the road.
+    pub normals: Vec<Vec2>,
+    pub room: Vec<Width>,
+    pub speeds: Vec<f32>,
+    pub closed: bool,
+}
+
+impl RacingLine {
+    pub fn new(centerline: &Centerline) -> Option<Self> {
+        let (centers, widths) = resample(centerline);
+        let count = centers.len();
+        if count < 2 {
+            return None;
+        }
+        let closed = centerline.closed;
+        let neighbours = |index: usize| -> (usize, usize) {
+            match (closed, index) {
+                (true, _) => ((index + count - 1) % count, (index + 1) % count),
+                (false, 0) => (0, 1),
+                (false, _) => (index - 1, (index + 1).min(count - 1)),
+            }
+        };
+        let normals: Vec<Vec2> = (0..count)
+            .map(|index| {
+                let (previous, next) = neighbours(index);
+                (centers[next] - centers[previous]).normalize().perp()
+            })
+            .collect();
+        let limits: Vec<(f32, f32)> = widths.iter()
+            .map(|width| (-(width.right - BORDER_MARGIN).max(0f32), (width.left - BORDER_MARGIN).max(0f32)))
+            .collect();
+
+        // every point moves towards the middle of its neighbours, that straightens
+        // the line and moves it to the inside of corners until it meets the borders.
+        let mut offsets = vec![0f32; count];
+        let movable = if closed { 0..count } else { 1..count - 1 };
+        for _pass in 0..SMOOTHING_PASSES {
+            for index in movable.clone() {
+                let (previous, next) = neighbours(index);
+                let point = |index: usize| centers[index] + normals[index] * offsets[index];
+                let middle = (point(previous) + point(next)) / 2f32;
+                let (min, max) = limits[index];
+                offsets[index] = (middle - centers[index]).dot(normals[index]).clamp(min, max);
+            }
+        }
+        let points: Vec<Vec2> = (0..count).map(|index| centers[index] + normals[index] * offsets[index]).collect();
+        let room = (0..count)
+            .map(|index| {
+                let (min, max) = limits[index];
+                Width { left: max - offsets[index], right: offsets[index] - min }
+            })
+            .collect();
+
+        let mut speeds: Vec<f32> = (0..count)
+            .map(|index| {
+                let (previous, next) = neighbours(index);
+                let (incoming, outgoing) = (points[index] - points[previous], points[next] - points[index]);
+                if incoming.length() <= f32::EPSILON || outgoing.length() <= f32::EPSILON {
+                    return TOP_SPEED;
+                }
+                let length = (incoming.length() + outgoing.length()) / 2f32;
+                let curvature = incoming.angle_between(outgoing).abs() / length;
+                (LATERAL_ACCELERATION / curvature.max(f32::EPSILON)).sqrt().min(TOP_SPEED)
+            })
+            .collect();
+        // cars have to slow down before corners, a closed line wraps once to reach its end.
+        let backwards: Vec<usize> = if closed {
+            (0..2 * count).map(|step| count - 1 - step % count).collect()
+        } else {
+            (0..count - 1).rev().collect()
+        };
+        for index in backwards {
+            let next = (index + 1) % count;
+            let distance = (points[next] - points[index]).length();
+            let reachable = (speeds[next] * speeds[next] + 2f32 * BRAKING * distance).sqrt();
+            speeds[index] = speeds[index].min(reachable);
+        }
+        Some(RacingLine { points, normals, room, speeds, closed })
+    }
+
+    fn next(&self, index: usize) -> Option<usize> {
+        match (self.closed, index + 1 < self.points.len()) {
+            (_, true) => Some(index + 1),
+            (true, false) => Some(0),
+            (false, false) => None,
+        }
+    }
+
+    /// Nearest point to `position`, near `hint` when there is one.
+    pub fn nearest(&self, position: Vec2, hint: Option<usize>) -> usize {
+        let count = self.points.len();
+        let candidates: Vec<usize> = match hint {
+            Some(hint) if self.closed => (0..SEARCH_WINDOW).map(|step| (hint + count - 2 + step) % count).collect(),
+            Some(hint) => (hint.saturating_sub(2)..(hint + SEARCH_WINDOW).min(count)).collect(),
+            None => (0..count).collect(),
+        };
+        let distance = |index: &usize| (self.points[*index] - position).length_squared();
+        let nearest = candidates.into_iter()
+            .min_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap_or(std::cmp::Ordering::Equal))
+            .unwrap_or(0);
+        match hint {
+            Some(_) if distance(&nearest).sqrt() > RESEARCH_DISTANCE => self.nearest(position, None),
+            _ => nearest,
+        }
+    }
+
+    /// Point `distance` further along the line, or its end.
+    pub fn ahead(&self, index: usize, distance: f32) -> usize {
+        let mut index = index;
+        let mut travelled = 0f32;
+        while travelled < distance {
+            let next = match self.next(index) {
+                Some(next) => next,
+                None => break,
+            };
+            travelled += (self.points[next] - self.points[index]).length();
+            index = next;
+        }
+        index
+    }
+}
+
+/// Steering of pure pursuit: the arc through the car and the target gives the wheel angle.
+/// Targets behind the car take the full lock to their side.
+fn pursue(vehicle: &Vehicle, position: Vec2, forward: Vec2, speed: f32, target: Vec2) -> f32 {
+    let to_target = target - position;
+    let side = to_target.dot(forward.perp());
+    if to_target.dot(forward) < 0f32 {
+        return side.signum();
+    }
+    let distance = to_target.length_squared().max(f32::EPSILON);
+    let wheel_angle = (2f32 * side / distance * vehicle.wheelbase).atan();
+    let max_angle = vehicle.steer_angle(1f32, speed).max(f32::EPSILON);
+    (wheel_angle / max_angle).clamp(-1f32, 1f32)
+}
+
+/// Centerline with points about `LINE_SPACING` apart, without the repeated start of a closed line.
+fn resample(centerline: &Centerline) -> (Vec<Vec2>, Vec<Width>) {
+    let mut points = Vec::new();
+    let mut widths = Vec::new();
+    let pairs = centerline.points.windows(2).zip(centerline.widths.windows(2));
+    for (segment, width) in pairs {
+        let (from, to) = (segment[0].truncate(), segment[1].truncate());
+        let length = (to - from).length();
+        if length <= f32::EPSILON {
+            continue;
+        }
+        let steps = (length / LINE_SPACING).ceil() as usize;
+        for step in 0..steps {
+            let ratio = step as f32 / steps as f32;
+            points.push(from + (to - from) * ratio);
+            widths.push(width[0].lerp(width[1], ratio));
+        }
+    }
+    if !centerline.closed {
+        if let (Some(point), Some(width)) = (centerline.points.last(), centerline.widths.last()) {
+            points.push(point.truncate());
+            widths.push(*width);
+        }
+    }
+    (points, widths)
+}
+
+fn racing_line(mut commands: Commands, centerlines: Query<(Entity, &Centerline), Added<Centerline>>) {
+    for (entity, centerline) in centerlines.iter() {
+        if let Some(line) = RacingLine::new(centerline) {
+            commands.entity(entity).insert(line);
+        }
+    }
+}
+
+/// Car in front of a driver, in the coordinates of the driver's car.
+struct Obstacle {
+    ahead: f32,
+    side: f32,
+    speed: f32,
+}
+
+fn drive(
+    mut commands: Commands,
+    lines: Query<&RacingLine>,
+    new_lines: Query<Entity, Added<RacingLine>>,
+    cars: Query<(Entity, &Simulated, &VehicleState), With<Vehicle>>,
+    mut drivers: Query<(Entity, &mut AiDriver, &Vehicle, &mut CarControls), Without<Eliminated>>,
+) {
+    let line = match lines.iter().next() {
+        Some(line) => line,
+        None => return,
+    };
+    let line_changed = new_lines.iter().next().is_some();
+    for (car, mut driver, vehicle, mut controls) in drivers.iter_mut() {
+        let (body, state) = match cars.get(car) {
+            Ok((_car, body, state)) => (body, state),
+            Err(_) => continue,
+        };
+        if line_changed {
+            driver.index = None;
+        }
+        let position = body.current.translation.truncate();
+        let forward = body.current.rotation.mul_vec3(Vec3::X).truncate().normalize();
+        let speed = state.velocity.length();
+        let forward_speed = state.velocity.dot(forward);
+        let index = line.nearest(position, driver.index);
+        driver.index = Some(index);
+
+        let obstacle = cars.iter()
+            .filter(|(other, _body, _state)| *other != car)
+            .map(|(_other, other_body, other_state)| {
+                let offset = other_body.current.translation.truncate() - position;
+                Obstacle {
+                    ahead: offset.dot(forward),
+                    side: offset.dot(forward.perp()),
+                    speed: other_state.velocity.dot(forward),
+                }
+            })
+            .filter(|obstacle| obstacle.ahead > 0f32 && obstacle.ahead < AVOID_DISTANCE)
+            .filter(|obstacle| obstacle.side.abs() < AVOID_WIDTH && obstacle.speed < forward_speed)
+            .min_by(|a, b| a.ahead.partial_cmp(&b.ahead).unwrap_or(std::cmp::Ordering::Equal));
+
+        // overtaking drivers pass on the side away from the car in front.
+        let room = line.room[index];
+        let wanted_offset = match &obstacle {
+            Some(obstacle) if obstacle.side > 0f32 => -room.right * driver.skill.aggression,
+            Some(_) => room.left * driver.skill.aggression,
+            None => 0f32,
+        };
+        let step = OFFSET_SPEED * TIMESTEP as f32;
+        driver.offset += (wanted_offset - driver.offset).clamp(-step, step);
+
+        let target_index = line.ahead(index, MIN_LOOKAHEAD + speed * driver.skill.lookahead);
+        let target_room = line.room[target_index];
+        let offset = driver.offset.clamp(-target_room.right, target_room.left);
+        let target = line.points[target_index] + line.normals[target_index] * offset;
+
+        controls.steer = pursue(vehicle, position, forward, speed, target);
+
+        let mut target_speed = line.speeds[index].min(line.speeds[target_index]) * driver.skill.pace;
+        if let Some(obstacle) = &obstacle {
+            if driver.skill.aggression <= 0f32 {
+                target_speed = target_speed.min(obstacle.speed.max(0f32));
+            }
+        }
+        let error = target_speed - forward_speed;
+        controls.throttle = (error / SPEED_BAND).clamp(0f32, 1f32);
+        // braking at a standstill would reverse the car.
+        controls.brake = if forward_speed > STOP_SPEED { (-error / SPEED_BAND).clamp(0f32, 1f32) } else { 0f32 };
+        controls.handbrake = false;
+        match driver.recover(forward_speed, target_speed) {
+            Recovery::Drive => {},
+            // backing off turns the car the other way round.
+            Recovery::Reverse => {
+                controls.throttle = 0f32;
+                controls.brake = 1f32;
+                controls.steer = -controls.steer;
+            },
+            Recovery::Respawn => {
+                commands.entity(car).insert(Respawn);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{vec2, vec3};
+    use super::*;
+
+    /// Square loop turning left from the origin, 40 to both borders.
+    fn square_loop(side: f32) -> RacingLine {
+        let corners = [(0f32, 0f32), (side, 0f32), (side, side), (0f32, side), (0f32, 0f32)];
+        let centerline = Centerline {
+            points: corners.iter().map(|(x, y)| vec3(*x, *y, 0f32)).collect(),
+            widths: vec![Width::symmetric(40f32); corners.len()],
+            closed: true,
+        };
+        RacingLine::new(&centerline).unwrap()
+    }
+
+    #[test]
+    fn racing_line_cuts_corners_inside_the_road() {
+        let line = square_loop(300f32);
+        assert_eq!(line.points.len(), 120);
+        for (index, room) in line.room.iter().enumerate() {
+            assert!(room.left >= -0.001 && room.right >= -0.001, "{:?} at {}", room, index);
+        }
+        // points are 10 apart, so corners of the road are every 30 points.
+        let center = vec2(150f32, 150f32);
+        for (index, corner) in [(30, vec2(300f32, 0f32)), (60, vec2(300f32, 300f32)), (90, vec2(0f32, 300f32))].iter() {
+            assert!(line.points[*index].distance(center) < corner.distance(center) - 10f32, "{}", line.points[*index]);
+        }
+    }
+
+    #[test]
+    fn speeds_leave_room_to_brake_for_corners() {
+        let line = square_loop(300f32);
+        let count = line.points.len();
+        assert!(line.speeds.iter().all(|speed| *speed > 0f32 && *speed <= TOP_SPEED));
+        let slowest = (0..count)
+            .min_by(|a, b| line.speeds[*a].partial_cmp(&line.speeds[*b]).unwrap())
+            .unwrap();
+        assert!(line.speeds[slowest] < TOP_SPEED);
+        assert!(line.speeds[slowest] < line.speeds[line.nearest(vec2(150f32, 0f32), None)]);
+        for index in 0..count {
+            let next = (index + 1) % count;
+            let distance = line.points[index].distance(line.points[next]);
+            let reachable = line.speeds[next] * line.speeds[next] + 2f32 * BRAKING * distance;
+            assert!(line.speeds[index] * line.speeds[index] <= reachable * 1.001, "too fast at {}", index);
+        }
+    }
+
+    #[test]
+    fn nearest_searches_the_whole_line_after_a_teleport() {
+        let line = square_loop(600f32);
+        let count = line.points.len();
+        assert_eq!(line.nearest(line.points[5], Some(3)), 5);
+        // the window wraps around the start of the loop.
+        assert_eq!(line.nearest(line.points[count - 1], Some(0)), count - 1);
+        // the opposite corner is out of the window of the start.
+        let opposite = line.nearest(vec2(600f32, 600f32), None);
+        assert_eq!(line.nearest(line.points[opposite], Some(0)), opposite);
+    }
+
+    #[test]
+    fn pure_pursuit_steers_towards_the_target() {
+        let vehicle = Vehicle::default();
+        let steer = |target: Vec2| pursue(&vehicle, Vec2::ZERO, Vec2::X, 200f32, target);
+        assert!(steer(vec2(100f32, 20f32)) > 0f32);
+        assert!(steer(vec2(100f32, -20f32)) < 0f32);
+        assert_eq!(steer(vec2(100f32, 0f32)), 0f32);
+        assert!(steer(vec2(100f32, 20f32)) < steer(vec2(50f32, 20f32)));
+        assert_eq!(steer(vec2(-50f32, 10f32)), 1f32);
+        assert_eq!(steer(vec2(-50f32, -10f32)), -1f32);
+    }
+
+    #[test]
+    fn stuck_drivers_back_off_then_respawn() {
+        let mut driver = AiDriver::new(Difficulty::Medium);
+        let ticks = |seconds: f32| (seconds / TIMESTEP as f32).round() as usize;
+        let recoveries: Vec<Recovery> = (0..=ticks(RESPAWN_TIME)).map(|_| driver.recover(0f32, 300f32)).collect();
+        // the time is summed tick by tick, so it may be one tick late.
+        let near = |tick: usize, seconds: f32| (tick as isize - ticks(seconds) as isize).abs() <= 1;
+        let reverse = recoveries.iter().position(|recovery| *recovery == Recovery::Reverse).unwrap();
+        let drive = reverse + recoveries[reverse..].iter().position(|recovery| *recovery != Recovery::Reverse).unwrap();
+        assert!(near(reverse, STUCK_TIME) && near(drive, STUCK_TIME + REVERSE_TIME), "{} {}", reverse, drive);
+        let respawn = recoveries.iter().position(|recovery| *recovery == Recovery::Respawn).unwrap();
+        assert!(near(respawn, RESPAWN_TIME), "{}", respawn);
+
+        // waiting behind a car or driving on is not being stuck.
+        for _tick in 0..ticks(STUCK_TIME) {
+            assert_eq!(driver.recover(0f32, 0f32), Recovery::Drive);
+            assert_eq!(driver.recover(0f32, 300f32), Recovery::Drive);
+            assert_eq!(driver.recover(100f32, 300f32), Recovery::Drive);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use bevy::{
+    app::{AppBuilder, EventWriter, Plugin},
+    ecs::{
+        entity::Entity,
+        schedule::SystemLabel,
+        system::{Commands, IntoSystem, Query},
+    },
+    math::{Vec2, Vec3},
+    prelude::ParallelSystemDescriptorCoercion,
+    transform::components::Transform,
+};
+use serde::{Deserialize, Serialize};
+use crate::collision::{self, Collider, ImpactEvent, Obb, Shape};
+use crate::respawn::Respawn;
+use crate::road::RoadMesh;
+use crate::spatial_hash::{Aabb, SpatialHash};
+use crate::simulation::{Simulated, SimulationStage, SimulationSystem, TIMESTEP};
+use crate::vehicle::{Vehicle, VehicleState};
+
+/// Size of the cells indexing road edges.
+const CELL_SIZE: f32 = 64f32;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+pub enum BoundaryPluginSystem {
+    OffRoad,
+}
+
+pub struct BoundaryPlugin;
+
+impl Plugin for BoundaryPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_stage(
+                SimulationStage,
+                off_road.system()
+                    .label(BoundaryPluginSystem::OffRoad)
+                    .label(SimulationSystem::Collision)
+                    .after(SimulationSystem::Movement));
+    }
+}
+
+/// What happens to a car leaving the road.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OffRoad {
+    /// The car keeps driving, but loses `drag` of its speed per second.
+    Grass { drag: f32 },
+    /// Road edges bounce the car back, `restitution` of the speed into the edge is kept.
+    Wall { restitution: f32 },
+    /// The car is respawned, see `RespawnPolicy`.
+    Reset,
+}
+
+impl Default for OffRoad {
+    fn default() -> Self {
+        OffRoad::Grass { drag: 3f32 }
+    }
+}
+
+/// Edges of the road in world coordinates, it lives next to `RoadMesh`.
+pub struct RoadBoundary {
+    pub segments: Vec<(Vec2, Vec2)>,
+    pub off_road: OffRoad,
+    index: SpatialHash<usize>,
+}
+
+impl RoadBoundary {
+    pub fn new(polylines: &[Vec<Vec3>], off_road: OffRoad) -> Self {
+        let segments: Vec<(Vec2, Vec2)> = polylines.iter()
+            .flat_map(|polyline| polyline.windows(2))
+            .map(|pair| (pair[0].truncate(), pair[1].truncate()))
+            .collect();
+        let mut index = SpatialHash::new(CELL_SIZE);
+        for (segment, (from, to)) in segments.iter().enumerate() {
+            index.insert(segment, Aabb::from_points(&[*from, *to]));
+        }
+        RoadBoundary { segments, off_road, index }
+    }
+
+    /// Segments touching the box.
+    pub fn segments_near(&self, aabb: Aabb) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        self.index.query(aabb)
+            .into_iter()
+            .map(move |segment| self.segments[segment])
+            .filter(move |(from, to)| Aabb::from_points(&[*from, *to]).intersects(&aabb))
+    }
+}
+
+fn off_road(
+    mut commands: Commands,
+    mut impact_events: EventWriter<ImpactEvent>,
+    roads: Query<(&RoadMesh, &RoadBoundary, &Transform)>,
+    mut cars: Query<(Entity, &Collider, &Vehicle, &mut VehicleState, &mut Simulated)>,
+) {
+    let (road, boundary, road_transform) = match roads.single() {
+        Ok(road) => road,
+        Err(_) => return,
+    };
+    let to_road = road_transform.compute_matrix().inverse();
+    for (car, collider, vehicle, mut state, mut body) in cars.iter_mut() {
+        match boundary.off_road {
+            OffRoad::Grass { drag } => {
+                if road.triangle_at(to_road.transform_point3(body.current.translation)).is_none() {
+                    state.velocity *= (-drag * TIMESTEP as f32).exp();
+                }
+            },
+            OffRoad::Wall { restitution } => {
+                let car_size = match collider.shape {
+                    Shape::Box { size } => size,
+                    _ => continue,
+                };
+                let car_aabb = Obb::from_transform(&body.current, car_size).aabb();
+                for segment in boundary.segments_near(car_aabb) {
+                    let car_box = Obb::from_transform(&body.current, car_size);
+                    if let Some(contact) = collision::collide_box_segment(&car_box, segment) {
+                        body.current.translation += (contact.normal * contact.depth).extend(0f32);
+                        let velocity = collision::respond(
+                            state.velocity, &contact, restitution, vehicle.impact_friction);
+                        let impulse = (velocity - state.velocity).length() * vehicle.mass;
+                        if impulse > 0f32 {
+                            impact_events.send(ImpactEvent { car, impulse });
+                        }
+                        state.velocity = velocity;
+                    }
+                }
+            },
+            OffRoad::Reset => {
+                if road.triangle_at(to_road.transform_point3(body.current.translation)).is_none() {
+                    commands.entity(car).insert(Respawn);
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{vec2, Quat};
+    use crate::road;
+    use crate::track::TrackDescription;
+    use super::*;
+
+    #[test]
+    fn default_track_has_no_walls_on_the_road() {
+        let track = TrackDescription::from_ron(include_str!("../assets/tracks/default.track.ron")).unwrap();
+        let sections = track.to_sections().unwrap();
+        let start = track.start_pivot(&sections);
+        let line = road::sections_into_line(start.position(), &sections);
+        let (road, _pivots) = road::generate_road(start, &sections, track.join, track.taper, track.is_closed());
+        let boundary = RoadBoundary::new(&road.boundary_polylines(), OffRoad::Wall { restitution: 0.3 });
+        // a car driving along the centerline.
+        for pair in line.windows(2) {
+            let direction = pair[1] - pair[0];
+            let rotation = Quat::from_rotation_z(direction.y.atan2(direction.x));
+            for step in 0..10 {
+                let translation = pair[0] + direction * step as f32 / 10f32;
+                let transform = Transform { translation, rotation, ..Default::default() };
+                let car = Obb::from_transform(&transform, vec2(36f32, 18f32));
+                let wall = boundary.segments_near(car.aabb())
+                    .find(|segment| collision::collide_box_segment(&car, *segment).is_some());
+                assert!(wall.is_none(), "wall {:?} at {}", wall, translation);
+            }
+        }
+    }
+}
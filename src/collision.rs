@@ -0,0 +1,514 @@
+use bevy::{
+    app::{AppBuilder, Plugin},
+    ecs::{
+        entity::Entity,
+        schedule::SystemLabel,
+        system::{IntoSystem, Query, ResMut},
+    },
+    math::{Vec2, Vec3},
+    prelude::ParallelSystemDescriptorCoercion,
+    transform::components::Transform,
+};
+use serde::{Deserialize, Serialize};
+use crate::simulation::{Simulated, SimulationStage, SimulationSystem};
+use crate::spatial_hash::{Aabb, SpatialHash};
+
+/// Size of the broad phase cells, a few car lengths.
+pub const CELL_SIZE: f32 = 64f32;
+/// Segments of a drawn circle, capsule ends get half of them each.
+const OUTLINE_SEGMENTS: usize = 16;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+pub enum CollisionPluginSystem {
+    Index,
+}
+
+pub struct CollisionPlugin;
+
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_event::<TriggerEvent>()
+            .add_event::<CarContactEvent>()
+            .add_event::<ImpactEvent>()
+            .insert_resource(ColliderIndex(SpatialHash::new(CELL_SIZE)))
+            .add_system_to_stage(
+                SimulationStage,
+                index_colliders.system()
+                    .label(CollisionPluginSystem::Index)
+                    .after(SimulationSystem::Movement)
+                    .before(SimulationSystem::Collision));
+    }
+}
+
+/// Broad phase of every collider, rebuilt on each tick after movement.
+pub struct ColliderIndex(pub SpatialHash<Entity>);
+
+fn index_colliders(mut index: ResMut<ColliderIndex>, colliders: Query<(Entity, &Collider, &Simulated)>) {
+    index.0.clear();
+    for (entity, collider, body) in colliders.iter() {
+        index.0.insert(entity, collider.shape.aabb(&body.current));
+    }
+}
+
+/// Collision layers as bits, a collider may be on several of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layers(pub u32);
+
+impl Layers {
+    pub const ALL: Layers = Layers(u32::MAX);
+    pub const CARS: Layers = Layers(1);
+    pub const WALLS: Layers = Layers(1 << 1);
+    pub const PICKUPS: Layers = Layers(1 << 2);
+
+    pub fn intersects(self, other: Layers) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl std::ops::BitOr for Layers {
+    type Output = Layers;
+
+    fn bitor(self, other: Layers) -> Layers {
+        Layers(self.0 | other.0)
+    }
+}
+
+/// Shapes are given in the coordinates of the collider transform.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Shape {
+    Box { size: Vec2 },
+    Circle { radius: f32 },
+    /// Segment along `X` from `-half_length` to `half_length`, widened by `radius`.
+    Capsule { half_length: f32, radius: f32 },
+    /// Open chain of segments, only its lines collide.
+    Polyline { points: Vec<Vec2> },
+    ConvexPolygon { points: Vec<Vec2> },
+}
+
+/// Two colliders interact when each one is on a layer of the other one's `mask`.
+/// Triggers do not push anything, they only report overlaps.
+#[derive(Debug, Clone)]
+pub struct Collider {
+    pub shape: Shape,
+    pub layers: Layers,
+    pub mask: Layers,
+    pub trigger: bool,
+}
+
+impl Collider {
+    pub fn solid(shape: Shape, layers: Layers) -> Self {
+        Collider { shape, layers, mask: Layers::ALL, trigger: false }
+    }
+
+    pub fn trigger(shape: Shape, layers: Layers) -> Self {
+        Collider { shape, layers, mask: Layers::CARS, trigger: true }
+    }
+
+    pub fn interacts(&self, other: &Collider) -> bool {
+        self.layers.intersects(other.mask) && other.layers.intersects(self.mask)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum TriggerEvent {
+    Entered { car: Entity, trigger: Entity },
+    Exited { car: Entity, trigger: Entity },
+}
+
+/// Two cars hit each other, `normal` points from `second` towards `first`
+/// and `impulse` is the momentum exchanged along it.
+#[derive(Debug, Clone)]
+pub struct CarContactEvent {
+    pub first: Entity,
+    pub second: Entity,
+    pub normal: Vec2,
+    pub impulse: f32,
+}
+
+/// A car hit something static, `impulse` is the momentum it lost.
+#[derive(Debug, Clone)]
+pub struct ImpactEvent {
+    pub car: Entity,
+    pub impulse: f32,
+}
+
+/// Oriented box on the XY plane.
+#[derive(Debug, Clone, Copy)]
+pub struct Obb {
+    pub center: Vec2,
+    pub half_size: Vec2,
+    /// Unit vectors along the width and the height of the box.
+    pub axes: [Vec2; 2],
+}
+
+/// How to move the first shape out of the second one:
+/// `normal` points away from the second shape, `depth` is the distance to go.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Contact {
+    pub normal: Vec2,
+    pub depth: f32,
+}
+
+impl Obb {
+    /// Box of `size` before the rotation and the scale of the transform.
+    pub fn from_transform(transform: &Transform, size: Vec2) -> Self {
+        let axis = |direction: Vec3| transform.rotation.mul_vec3(direction).truncate().normalize();
+        Obb {
+            center: transform.translation.truncate(),
+            half_size: (size * transform.scale.truncate()).abs() / 2f32,
+            axes: [axis(Vec3::X), axis(Vec3::Y)],
+        }
+    }
+
+    /// Half length of the box projected on the unit `axis`.
+    fn radius(&self, axis: Vec2) -> f32 {
+        self.axes[0].dot(axis).abs() * self.half_size.x
+            + self.axes[1].dot(axis).abs() * self.half_size.y
+    }
+
+    fn project(&self, axis: Vec2) -> (f32, f32) {
+        let center = self.center.dot(axis);
+        let radius = self.radius(axis);
+        (center - radius, center + radius)
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let extent = Vec2::new(self.radius(Vec2::X), self.radius(Vec2::Y));
+        Aabb { min: self.center - extent, max: self.center + extent }
+    }
+
+    fn closest_point(&self, point: Vec2) -> Vec2 {
+        let offset = point - self.center;
+        let x = offset.dot(self.axes[0]).clamp(-self.half_size.x, self.half_size.x);
+        let y = offset.dot(self.axes[1]).clamp(-self.half_size.y, self.half_size.y);
+        self.center + self.axes[0] * x + self.axes[1] * y
+    }
+}
+
+impl Shape {
+    /// Reason why the shape can not collide, if any.
+    pub fn check(&self) -> Result<(), &'static str> {
+        match self {
+            Shape::Box { size } if size.x <= 0f32 || size.y <= 0f32 => Err("box size must be positive"),
+            Shape::Circle { radius } | Shape::Capsule { radius, .. } if *radius <= 0f32 =>
+                Err("radius must be positive"),
+            Shape::Capsule { half_length, .. } if *half_length < 0f32 => Err("capsule length must not be negative"),
+            Shape::Polyline { points } if points.len() < 2 => Err("polyline needs at least two points"),
+            Shape::ConvexPolygon { points } => {
+                if points.len() < 3 {
+                    return Err("polygon needs at least three points");
+                }
+                let count = points.len();
+                let turns: Vec<f32> = (0..count)
+                    .map(|index| {
+                        let (a, b, c) = (points[index], points[(index + 1) % count], points[(index + 2) % count]);
+                        (b - a).perp_dot(c - b)
+                    })
+                    .collect();
+                if turns.iter().all(|turn| *turn > 0f32) || turns.iter().all(|turn| *turn < 0f32) {
+                    Ok(())
+                } else {
+                    Err("polygon must be convex")
+                }
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Points around the shape for drawing, closed outlines end at their first point.
+    pub fn outline(&self) -> Vec<Vec2> {
+        let arc = |center: Vec2, radius: f32, from: f32, sweep: f32, segments: usize| {
+            (0..=segments).map(move |step| {
+                let angle = from + sweep * step as f32 / segments as f32;
+                center + Vec2::new(angle.cos(), angle.sin()) * radius
+            })
+        };
+        let half_turn = std::f32::consts::PI;
+        let mut points: Vec<Vec2> = match self {
+            Shape::Box { size } => {
+                let half = *size / 2f32;
+                vec![half, Vec2::new(-half.x, half.y), -half, Vec2::new(half.x, -half.y)]
+            },
+            Shape::Circle { radius } => arc(Vec2::ZERO, *radius, 0f32, 2f32 * half_turn, OUTLINE_SEGMENTS)
+                .take(OUTLINE_SEGMENTS)
+                .collect(),
+            Shape::Capsule { half_length, radius } => {
+                let end = Vec2::new(*half_length, 0f32);
+                arc(end, *radius, -half_turn / 2f32, half_turn, OUTLINE_SEGMENTS / 2)
+                    .chain(arc(-end, *radius, half_turn / 2f32, half_turn, OUTLINE_SEGMENTS / 2))
+                    .collect()
+            },
+            Shape::Polyline { points } => return points.clone(),
+            Shape::ConvexPolygon { points } => points.clone(),
+        };
+        if let Some(first) = points.first().copied() {
+            if points.last() != Some(&first) {
+                points.push(first);
+            }
+        }
+        points
+    }
+
+    pub fn aabb(&self, transform: &Transform) -> Aabb {
+        let to_world = |point: &Vec2| transform.mul_vec3(point.extend(0f32)).truncate();
+        let scale = transform.scale.x.abs().max(transform.scale.y.abs());
+        match self {
+            Shape::Box { size } => Obb::from_transform(transform, *size).aabb(),
+            Shape::Circle { radius } => {
+                let center = transform.translation.truncate();
+                Aabb { min: center, max: center }.grow(radius * scale)
+            },
+            Shape::Capsule { half_length, radius } => {
+                let ends = [
+                    to_world(&Vec2::new(-half_length, 0f32)),
+                    to_world(&Vec2::new(*half_length, 0f32)),
+                ];
+                Aabb::from_points(&ends).grow(radius * scale)
+            },
+            Shape::Polyline { points } | Shape::ConvexPolygon { points } => {
+                let points: Vec<Vec2> = points.iter().map(to_world).collect();
+                Aabb::from_points(&points)
+            },
+        }
+    }
+
+    /// Collides the box with the shape placed by `transform`.
+    pub fn collide_box(&self, a: &Obb, transform: &Transform) -> Option<Contact> {
+        let to_world = |point: &Vec2| transform.mul_vec3(point.extend(0f32)).truncate();
+        let scale = transform.scale.x.abs().max(transform.scale.y.abs());
+        match self {
+            Shape::Box { size } => collide_boxes(a, &Obb::from_transform(transform, *size)),
+            Shape::Circle { radius } => {
+                let center = transform.translation.truncate();
+                collide_box_capsule(a, (center, center), radius * scale)
+            },
+            Shape::Capsule { half_length, radius } => {
+                let ends = (
+                    to_world(&Vec2::new(-half_length, 0f32)),
+                    to_world(&Vec2::new(*half_length, 0f32)),
+                );
+                collide_box_capsule(a, ends, radius * scale)
+            },
+            Shape::Polyline { points } => {
+                let points: Vec<Vec2> = points.iter().map(to_world).collect();
+                points.windows(2)
+                    .filter_map(|pair| collide_box_segment(a, (pair[0], pair[1])))
+                    .fold(None, |deepest: Option<Contact>, contact| match deepest {
+                        Some(deepest) if deepest.depth >= contact.depth => Some(deepest),
+                        _ => Some(contact),
+                    })
+            },
+            Shape::ConvexPolygon { points } => {
+                let points: Vec<Vec2> = points.iter().map(to_world).collect();
+                collide_box_polygon(a, &points)
+            },
+        }
+    }
+}
+
+/// Separating axis test, only the axes of both boxes are required in 2D.
+pub fn collide_boxes(a: &Obb, b: &Obb) -> Option<Contact> {
+    let axes = [a.axes[0], a.axes[1], b.axes[0], b.axes[1]];
+    separate(a, |axis| b.project(axis), &axes)
+}
+
+pub fn collide_box_segment(a: &Obb, (from, to): (Vec2, Vec2)) -> Option<Contact> {
+    let along = to - from;
+    if along.length() <= f32::EPSILON {
+        return None;
+    }
+    let axes = [a.axes[0], a.axes[1], along.perp().normalize()];
+    separate(a, |axis| project_points(&[from, to], axis), &axes)
+}
+
+/// Capsule around the segment, a circle when both ends are the same.
+/// Rounded ends add the axes towards the closest points of the box.
+pub fn collide_box_capsule(a: &Obb, (from, to): (Vec2, Vec2), radius: f32) -> Option<Contact> {
+    let mut axes = vec![a.axes[0], a.axes[1]];
+    let along = to - from;
+    if along.length() > f32::EPSILON {
+        axes.push(along.perp().normalize());
+    }
+    for end in [from, to].iter() {
+        let towards = *end - a.closest_point(*end);
+        if towards.length() > f32::EPSILON {
+            axes.push(towards.normalize());
+        }
+    }
+    let project = |axis| {
+        let (min, max) = project_points(&[from, to], axis);
+        (min - radius, max + radius)
+    };
+    separate(a, project, &axes)
+}
+
+pub fn collide_box_polygon(a: &Obb, points: &[Vec2]) -> Option<Contact> {
+    let count = points.len();
+    let mut axes = vec![a.axes[0], a.axes[1]];
+    for index in 0..count {
+        let edge = points[(index + 1) % count] - points[index];
+        if edge.length() > f32::EPSILON {
+            axes.push(edge.perp().normalize());
+        }
+    }
+    separate(a, |axis| project_points(points, axis), &axes)
+}
+
+fn project_points(points: &[Vec2], axis: Vec2) -> (f32, f32) {
+    points.iter()
+        .map(|point| point.dot(axis))
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| (min.min(value), max.max(value)))
+}
+
+/// Finds the axis with the smallest overlap of `a` and a convex shape,
+/// `project` gives the interval covered by the shape on an axis.
+fn separate(a: &Obb, project: impl Fn(Vec2) -> (f32, f32), axes: &[Vec2]) -> Option<Contact> {
+    let mut contact: Option<Contact> = None;
+    for axis in axes.iter() {
+        let (a_min, a_max) = a.project(*axis);
+        let (b_min, b_max) = project(*axis);
+        let (depth, normal) = if a_max - b_min < b_max - a_min {
+            (a_max - b_min, -*axis)
+        } else {
+            (b_max - a_min, *axis)
+        };
+        if depth <= 0f32 {
+            return None;
+        }
+        let closer = match contact {
+            Some(contact) => depth < contact.depth,
+            None => true,
+        };
+        if closer {
+            contact = Some(Contact { normal, depth });
+        }
+    }
+    contact
+}
+
+/// Velocity after hitting a static surface. The part along `normal` bounces
+/// with `restitution`, the part along the surface loses up to `friction` of itself,
+/// more for head-on impacts than for glancing ones.
+pub fn respond(velocity: Vec2, contact: &Contact, restitution: f32, friction: f32) -> Vec2 {
+    let into_surface = velocity.dot(contact.normal);
+    let speed = velocity.length();
+    if into_surface >= 0f32 || speed <= f32::EPSILON {
+        return velocity;
+    }
+    let along_surface = velocity - contact.normal * into_surface;
+    let impact = -into_surface / speed;
+    along_surface * (1f32 - friction * impact).max(0f32) - contact.normal * into_surface * restitution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::math::{vec2, vec3, Quat};
+
+    fn obb(center: Vec2, size: Vec2, angle: f32) -> Obb {
+        let transform = Transform {
+            translation: center.extend(0f32),
+            rotation: Quat::from_rotation_z(angle),
+            ..Default::default()
+        };
+        Obb::from_transform(&transform, size)
+    }
+
+    fn assert_contact(contact: Option<Contact>, normal: Vec2, depth: f32) {
+        let contact = contact.expect("shapes should overlap");
+        assert!(contact.normal.abs_diff_eq(normal, 1e-4), "normal {:?}", contact.normal);
+        assert!((contact.depth - depth).abs() < 1e-3, "depth {}", contact.depth);
+    }
+
+    #[test]
+    fn axis_aligned_boxes_separate_along_the_shallowest_axis() {
+        let a = obb(Vec2::ZERO, vec2(20f32, 10f32), 0f32);
+        let b = obb(vec2(15f32, 2f32), vec2(20f32, 10f32), 0f32);
+        assert_contact(collide_boxes(&a, &b), -Vec2::X, 5f32);
+        assert_contact(collide_boxes(&b, &a), Vec2::X, 5f32);
+    }
+
+    /// The corner of the turned box pokes 10 - (22 - 10√2) into the first one.
+    #[test]
+    fn rotated_box_corner_pushes_along_the_face_it_hits() {
+        let a = obb(Vec2::ZERO, vec2(20f32, 20f32), 0f32);
+        let b = obb(vec2(22f32, 0f32), vec2(20f32, 20f32), std::f32::consts::FRAC_PI_4);
+        let depth = 10f32 - (22f32 - 10f32 * std::f32::consts::SQRT_2);
+        assert_contact(collide_boxes(&a, &b), -Vec2::X, depth);
+    }
+
+    #[test]
+    fn separated_boxes_do_not_collide() {
+        let a = obb(Vec2::ZERO, vec2(20f32, 10f32), 0f32);
+        assert_eq!(collide_boxes(&a, &obb(vec2(25f32, 0f32), vec2(20f32, 10f32), 0f32)), None);
+        // the bounding boxes overlap but the turned box misses the corner.
+        let turned = obb(vec2(18f32, 13f32), vec2(20f32, 10f32), std::f32::consts::FRAC_PI_4);
+        assert!(a.aabb().intersects(&turned.aabb()));
+        assert_eq!(collide_boxes(&a, &turned), None);
+    }
+
+    #[test]
+    fn box_is_pushed_off_the_segment_side_it_is_on() {
+        let a = obb(Vec2::ZERO, vec2(20f32, 10f32), 0f32);
+        let above = (vec2(-50f32, 4f32), vec2(50f32, 4f32));
+        assert_contact(collide_box_segment(&a, above), -Vec2::Y, 1f32);
+        // the direction of the segment does not matter.
+        assert_contact(collide_box_segment(&a, (above.1, above.0)), -Vec2::Y, 1f32);
+        let turned = obb(vec2(0f32, -10f32), vec2(20f32, 10f32), std::f32::consts::FRAC_PI_2);
+        assert_contact(collide_box_segment(&turned, (vec2(-50f32, -1f32), vec2(50f32, -1f32))), -Vec2::Y, 1f32);
+        assert_eq!(collide_box_segment(&a, (vec2(-50f32, 6f32), vec2(50f32, 6f32))), None);
+    }
+
+    fn at(x: f32, y: f32, angle: f32) -> Transform {
+        Transform { translation: vec3(x, y, 0f32), rotation: Quat::from_rotation_z(angle), ..Default::default() }
+    }
+
+    #[test]
+    fn circles_push_boxes_away_from_their_center() {
+        let a = obb(Vec2::ZERO, vec2(20f32, 10f32), 0f32);
+        let circle = Shape::Circle { radius: 5f32 };
+        assert_contact(circle.collide_box(&a, &at(0f32, 8f32, 0f32)), -Vec2::Y, 2f32);
+        // on the corner the push goes along the diagonal, 5 - 6/√2 deep.
+        let diagonal = -Vec2::ONE.normalize();
+        assert_contact(circle.collide_box(&a, &at(13f32, 8f32, 0f32)), diagonal, 5f32 - 6f32 / std::f32::consts::SQRT_2);
+        // the bounds overlap, but the rounded side misses the corner.
+        assert_eq!(circle.collide_box(&a, &at(14f32, 9f32, 0f32)), None);
+    }
+
+    #[test]
+    fn capsules_follow_their_transform() {
+        let a = obb(Vec2::ZERO, vec2(20f32, 10f32), 0f32);
+        let capsule = Shape::Capsule { half_length: 20f32, radius: 3f32 };
+        let upright = at(12f32, 0f32, std::f32::consts::FRAC_PI_2);
+        assert_contact(capsule.collide_box(&a, &upright), -Vec2::X, 1f32);
+        // lying along X it ends before reaching the box.
+        assert_eq!(capsule.collide_box(&a, &at(34f32, 0f32, 0f32)), None);
+        assert_contact(capsule.collide_box(&a, &at(32f32, 0f32, 0f32)), -Vec2::X, 1f32);
+    }
+
+    #[test]
+    fn polygons_and_polylines_push_along_their_shallowest_side() {
+        let a = obb(Vec2::ZERO, vec2(20f32, 10f32), 0f32);
+        let wedge = Shape::ConvexPolygon { points: vec![vec2(0f32, 4f32), vec2(10f32, 20f32), vec2(-10f32, 20f32)] };
+        assert_contact(wedge.collide_box(&a, &at(0f32, 0f32, 0f32)), -Vec2::Y, 1f32);
+        assert_eq!(wedge.collide_box(&a, &at(0f32, 2f32, 0f32)), None);
+        // the deepest of the segments wins.
+        let corner = Shape::Polyline { points: vec![vec2(-50f32, 4f32), vec2(8f32, 4f32), vec2(8f32, -50f32)] };
+        assert_contact(corner.collide_box(&a, &at(0f32, 0f32, 0f32)), -Vec2::X, 2f32);
+    }
+
+    #[test]
+    fn shapes_are_checked_and_outlined() {
+        assert_eq!(Shape::Circle { radius: 0f32 }.check(), Err("radius must be positive"));
+        let square = vec![Vec2::ZERO, Vec2::X, Vec2::ONE, Vec2::Y];
+        assert_eq!(Shape::ConvexPolygon { points: square.clone() }.check(), Ok(()));
+        let reversed = square.iter().rev().copied().collect();
+        assert_eq!(Shape::ConvexPolygon { points: reversed }.check(), Ok(()));
+        let outline = Shape::ConvexPolygon { points: square }.outline();
+        assert_eq!((outline.len(), outline.first(), outline.last()), (5, Some(&Vec2::ZERO), Some(&Vec2::ZERO)));
+        let capsule = Shape::Capsule { half_length: 10f32, radius: 2f32 }.outline();
+        assert_eq!(capsule.len(), OUTLINE_SEGMENTS + 3);
+        assert!(capsule.iter().all(|point| point.x.abs() <= 12.001 && point.y.abs() <= 2.001));
+    }
+}
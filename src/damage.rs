@@ -0,0 +1,135 @@
+use bevy::{
+    app::{AppBuilder, EventReader, EventWriter, Plugin},
+    ecs::{
+        entity::Entity,
+        query::Without,
+        schedule::SystemLabel,
+        system::{Commands, IntoSystem, Query, Res},
+    },
+    log::{info, warn},
+    prelude::ParallelSystemDescriptorCoercion,
+};
+use crate::collision::{CarContactEvent, ImpactEvent};
+use crate::simulation::{SimulationStage, SimulationSystem};
+
+/// Cars below this part of their health handle worse.
+const DAMAGED: f32 = 0.5;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+pub enum DamagePluginSystem {
+    Damage,
+    Log,
+}
+
+pub struct DamagePlugin;
+
+impl Plugin for DamagePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<DamageModel>()
+            .add_event::<DamageEvent>()
+            .add_event::<DestroyedEvent>()
+            .add_system_to_stage(
+                SimulationStage,
+                damage.system()
+                    .label(DamagePluginSystem::Damage)
+                    .after(SimulationSystem::Collision))
+            .add_system(damage_log.system().label(DamagePluginSystem::Log));
+    }
+}
+
+/// Impacts weaker than `threshold` leave no marks,
+/// the rest of an impulse takes `per_impulse` health per unit, up to `max_per_impact`.
+/// By default a wall hit at top speed takes about 30 health and one at half of it about 10.
+#[derive(Debug, Clone)]
+pub struct DamageModel {
+    pub threshold: f32,
+    pub per_impulse: f32,
+    pub max_per_impact: f32,
+}
+
+impl Default for DamageModel {
+    fn default() -> Self {
+        DamageModel {
+            threshold: 150_000f32,
+            per_impulse: 0.00005f32,
+            max_per_impact: 35f32,
+        }
+    }
+}
+
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Health { current: max, max }
+    }
+
+    /// Handling of the car from 1 for a healthy one to 0 for a wreck.
+    pub fn condition(&self) -> f32 {
+        if self.current <= 0f32 {
+            return 0f32;
+        }
+        let ratio = self.current / self.max;
+        (DAMAGED + ratio).min(1f32)
+    }
+}
+
+/// Marks a car with no health left, it is out of the race.
+pub struct Eliminated;
+
+#[derive(Debug, Clone)]
+pub struct DamageEvent {
+    pub car: Entity,
+    pub amount: f32,
+    pub health: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct DestroyedEvent {
+    pub car: Entity,
+}
+
+fn damage(
+    mut commands: Commands,
+    model: Res<DamageModel>,
+    mut impacts: EventReader<ImpactEvent>,
+    mut contacts: EventReader<CarContactEvent>,
+    mut damage_events: EventWriter<DamageEvent>,
+    mut destroyed_events: EventWriter<DestroyedEvent>,
+    mut cars: Query<&mut Health, Without<Eliminated>>,
+) {
+    let hits = impacts.iter()
+        .map(|impact| (impact.car, impact.impulse))
+        .chain(contacts.iter().flat_map(|contact| {
+            vec![(contact.first, contact.impulse), (contact.second, contact.impulse)]
+        }));
+    for (car, impulse) in hits {
+        let amount = ((impulse - model.threshold).max(0f32) * model.per_impulse).min(model.max_per_impact);
+        if amount <= 0f32 {
+            continue;
+        }
+        let mut health = match cars.get_mut(car) {
+            Ok(health) if health.current > 0f32 => health,
+            _ => continue,
+        };
+        health.current = (health.current - amount).max(0f32);
+        damage_events.send(DamageEvent { car, amount, health: health.current });
+        if health.current <= 0f32 {
+            commands.entity(car).insert(Eliminated);
+            destroyed_events.send(DestroyedEvent { car });
+        }
+    }
+}
+
+fn damage_log(mut damage_events: EventReader<DamageEvent>, mut destroyed_events: EventReader<DestroyedEvent>) {
+    for event in damage_events.iter() {
+        info!("Car {:?} took {:.1} damage, {:.1} health left", event.car, event.amount, event.health);
+    }
+    for event in destroyed_events.iter() {
+        warn!("Car {:?} is destroyed", event.car);
+    }
+}
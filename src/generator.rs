@@ -0,0 +1,259 @@
+use std::f32::consts::PI;
+use std::fmt;
+use bevy::math::{vec3, Vec3};
+use serde::{Deserialize, Serialize};
+use crate::road::{self, SectionDescription, Width};
+use crate::spline::{self, Cubic};
+
+/// Every attempt relaxes the corners that many times before giving up on its points.
+const RELAX_ITERATIONS: usize = 16;
+const SAMPLE_TOLERANCE: f32 = 1f32;
+
+/// Parameters of a closed procedural track, the same settings always give the same track.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GeneratorSettings {
+    pub seed: u64,
+    /// Approximate length of the centerline.
+    pub length: f32,
+    /// Corners per 1000 units of length.
+    pub turn_density: f32,
+    pub min_corner_radius: f32,
+    /// Smallest and largest road width.
+    pub width: (f32, f32),
+    #[serde(default = "default_attempts")]
+    pub attempts: usize,
+}
+
+fn default_attempts() -> usize {
+    32
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeneratorError {
+    InvalidSettings(&'static str),
+    /// Every attempt produced a track overlapping itself or with too sharp corners.
+    Exhausted { attempts: usize },
+}
+
+impl fmt::Display for GeneratorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeneratorError::InvalidSettings(reason) =>
+                write!(f, "Invalid generator settings: {}", reason),
+            GeneratorError::Exhausted { attempts } =>
+                write!(f, "Failed to generate a track in {} attempts", attempts),
+        }
+    }
+}
+
+impl std::error::Error for GeneratorError {}
+
+impl GeneratorSettings {
+    pub fn validate(&self) -> Result<(), GeneratorError> {
+        let (min_width, max_width) = self.width;
+        if self.length <= 0f32 {
+            return Err(GeneratorError::InvalidSettings("length must be positive"));
+        }
+        if self.turn_density <= 0f32 {
+            return Err(GeneratorError::InvalidSettings("turn density must be positive"));
+        }
+        if self.min_corner_radius < 0f32 {
+            return Err(GeneratorError::InvalidSettings("corner radius must not be negative"));
+        }
+        if min_width < 0f32 || min_width > max_width {
+            return Err(GeneratorError::InvalidSettings("width range must be ascending and positive"));
+        }
+        Ok(())
+    }
+
+    /// Closed loop starting and ending at the origin, one section per corner.
+    pub fn generate(&self) -> Result<Vec<SectionDescription>, GeneratorError> {
+        self.validate()?;
+        let mut random = SplitMix64::new(self.seed);
+        let corners = ((self.length / 1000f32 * self.turn_density).round() as usize).max(4);
+        let radius = self.length / (2f32 * PI);
+        for _attempt in 0..self.attempts {
+            let (mut points, widths) = self.control_points(&mut random, corners, radius);
+            for _iteration in 0..RELAX_ITERATIONS {
+                let pieces = sample_loop(&points);
+                let sections = to_sections(&pieces, &widths);
+                if !road::find_overlaps(Vec3::ZERO, &sections).is_empty() {
+                    break;
+                }
+                let line: Vec<Vec3> = pieces.iter().flatten().copied().collect();
+                if min_corner_radius(&line) >= self.min_corner_radius {
+                    return Ok(sections);
+                }
+                points = relax(&points);
+            }
+        }
+        Err(GeneratorError::Exhausted { attempts: self.attempts })
+    }
+
+    /// Points ordered by angle around the center, so their polygon never crosses itself.
+    fn control_points(
+        &self,
+        random: &mut SplitMix64,
+        corners: usize,
+        radius: f32,
+    ) -> (Vec<Vec3>, Vec<f32>) {
+        let (min_width, max_width) = self.width;
+        let step = 2f32 * PI / corners as f32;
+        let points = (0..corners)
+            .map(|corner| {
+                let angle = step * (corner as f32 + random.range(-0.3f32, 0.3f32));
+                let distance = radius * random.range(0.6f32, 1.4f32);
+                vec3(angle.cos(), angle.sin(), 0f32) * distance
+            })
+            .collect();
+        let widths = (0..corners)
+            .map(|_| random.range(min_width, max_width))
+            .collect();
+        (points, widths)
+    }
+}
+
+/// Samples closed Catmull-Rom loop, piece `n` goes from point `n` to the next one.
+/// The loop is moved so it starts at the origin.
+fn sample_loop(points: &[Vec3]) -> Vec<Vec<Vec3>> {
+    let count = points.len();
+    let at = |index: usize| points[index % count] - points[0];
+    (0..count)
+        .map(|index| {
+            let curve = Cubic::catmull_rom(at(index + count - 1), at(index), at(index + 1), at(index + 2));
+            spline::sample(&[curve], SAMPLE_TOLERANCE)
+        })
+        .collect()
+}
+
+/// Moves every point towards its neighbours, it makes corners wider.
+fn relax(points: &[Vec3]) -> Vec<Vec3> {
+    let count = points.len();
+    (0..count)
+        .map(|index| {
+            let previous = points[(index + count - 1) % count];
+            let next = points[(index + 1) % count];
+            (previous + points[index] * 2f32 + next) / 4f32
+        })
+        .collect()
+}
+
+fn to_sections(pieces: &[Vec<Vec3>], widths: &[f32]) -> Vec<SectionDescription> {
+    let count = pieces.len();
+    let mut start = Vec3::ZERO;
+    pieces.iter()
+        .enumerate()
+        .map(|(index, piece)| {
+            let (from, to) = (widths[index], widths[(index + 1) % count]);
+            piece.iter()
+                .enumerate()
+                .map(|(point_index, point)| {
+                    let shift = *point - start;
+                    start = *point;
+                    let ratio = (point_index + 1) as f32 / piece.len() as f32;
+                    (Width::symmetric(from + (to - from) * ratio), shift)
+                })
+                .filter(|(_width, shift)| shift.length() > f32::EPSILON)
+                .collect()
+        })
+        .collect()
+}
+
+/// Estimates turning radius at every vertex of the closed line.
+fn min_corner_radius(line: &[Vec3]) -> f32 {
+    let count = line.len();
+    (0..count)
+        .filter_map(|index| {
+            let previous = line[(index + count - 1) % count];
+            let current = line[index];
+            let next = line[(index + 1) % count];
+            let (incoming, outgoing) = (current - previous, next - current);
+            let angle = incoming.angle_between(outgoing);
+            (angle > f32::EPSILON)
+                .then(|| (incoming.length() + outgoing.length()) / 2f32 / angle)
+        })
+        .fold(f32::INFINITY, f32::min)
+}
+
+/// Small generator with a fixed algorithm, so seeds stay valid across dependency updates.
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut value = self.state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        value ^ (value >> 31)
+    }
+
+    /// Uniform value in `[from, to)`.
+    fn range(&mut self, from: f32, to: f32) -> f32 {
+        let unit = (self.next() >> 40) as f32 / (1u64 << 24) as f32;
+        from + (to - from) * unit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Settings of the random track asset.
+    fn settings(seed: u64) -> GeneratorSettings {
+        GeneratorSettings {
+            seed,
+            length: 4000f32,
+            turn_density: 3f32,
+            min_corner_radius: 60f32,
+            width: (20f32, 40f32),
+            attempts: default_attempts(),
+        }
+    }
+
+    /// Points at the end of every shift, the last one is the origin again.
+    fn line(sections: &[SectionDescription]) -> Vec<Vec3> {
+        let mut position = Vec3::ZERO;
+        sections.iter()
+            .flatten()
+            .map(|(_width, shift)| {
+                position += *shift;
+                position
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_track() {
+        let track = settings(42).generate().unwrap();
+        assert_eq!(track, settings(42).generate().unwrap());
+        assert_ne!(track, settings(43).generate().unwrap());
+    }
+
+    #[test]
+    fn generated_tracks_are_clean_closed_loops() {
+        for seed in 0..8 {
+            let settings = settings(seed);
+            let sections = settings.generate().unwrap();
+            let line = line(&sections);
+            assert!(line.last().unwrap().length() < 0.01, "seed {} ends at {}", seed, line.last().unwrap());
+            assert!(min_corner_radius(&line) >= settings.min_corner_radius, "seed {}", seed);
+            assert_eq!(road::find_overlaps(Vec3::ZERO, &sections), vec![], "seed {}", seed);
+            let (min_width, max_width) = settings.width;
+            for (width, _shift) in sections.iter().flatten() {
+                assert!(width.left >= min_width && width.left <= max_width, "seed {} width {:?}", seed, width);
+            }
+        }
+    }
+
+    #[test]
+    fn impossible_corners_exhaust_the_attempts() {
+        let settings = GeneratorSettings { min_corner_radius: 100_000f32, attempts: 2, ..settings(1) };
+        assert_eq!(settings.generate(), Err(GeneratorError::Exhausted { attempts: 2 }));
+    }
+}
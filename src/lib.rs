@@ -0,0 +1,5 @@
+//! Physics parts of the game that do not need a window, shared with the benches.
+
+pub mod collision;
+pub mod simulation;
+pub mod spatial_hash;
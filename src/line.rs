@@ -247,6 +247,9 @@ fn draw_lines_with_mesh(
                         line);
                     point_counter += line.points.len() * 2;
                 }
+                // lines could be despawned, so leftovers have to be erased.
+                positions.truncate(point_counter);
+                shader.colors.truncate(point_counter);
                 //println!("Positions: {:?}", positions);
             },
             other => panic!("Invalid type of positions {:#?}", other)
@@ -1,14 +1,25 @@
+mod actions;
+mod ai;
+mod generator;
+mod race;
 mod resources;
+mod respawn;
 mod road;
+mod spline;
+mod split_screen;
+mod track;
+mod vehicle;
 pub mod line;
 mod cursor;
+mod damage;
+mod boundary;
+use platformer::{collision, simulation, spatial_hash};
 use bevy::input::mouse::{MouseButtonInput, MouseMotion, MouseWheel};
-use line::{LineBundle, create_line};
-use bevy::prelude::{EventReader, info, shape};
-use bevy::render::camera::OrthographicProjection;
+use line::create_line;
+use bevy::prelude::{EventReader, EventWriter, info, shape, ParallelSystemDescriptorCoercion, SystemLabel};
 use bevy::window::{CursorMoved, Windows};
-use bevy::sprite::collide_aabb::collide;
-use bevy::ecs::query::Without;
+use bevy::ecs::query::{With, Without};
+use bevy::ecs::entity::Entity;
 use bevy::ecs::system::{
     IntoSystem,
     Query,
@@ -16,146 +27,342 @@ use bevy::ecs::system::{
     Res,
     ResMut
 };
-use bevy::input::{Input, keyboard::KeyCode};
 use bevy::sprite::ColorMaterial;
 use bevy::render::color::Color;
 use bevy::render::mesh::Mesh;
 use bevy::asset::Assets;
-use bevy::core::Time;
-use bevy::math::{Vec2, Vec3, Quat, vec2, vec3};
+use bevy::math::{Vec2, Vec3, vec2, vec3};
 use bevy::transform::components::Transform;
-use bevy::render::entity::OrthographicCameraBundle;
+use actions::{ActionState, Bindings, Player};
+use simulation::Simulated;
+use spatial_hash::SpatialHash;
+use collision::{CarContactEvent, Collider, ColliderIndex, ImpactEvent, Layers, Obb, Shape, TriggerEvent};
 use bevy::sprite::{Sprite, entity::SpriteBundle};
 use bevy::ecs::bundle::Bundle;
 
+#[derive(Default)]
+struct Car {
+    contacts: Vec<collision::Contact>,
+    /// Triggers the car is inside of.
+    triggers: Vec<Entity>,
+}
+
+struct Name(String);
+
+struct Wall;
+
+/// Everything a racing car has, whoever drives it.
 #[derive(Bundle)]
-struct HeroBundle {
+struct CarBundle {
+    #[bundle]
+    sprite: SpriteBundle,
+    car: Car,
     name: Name,
-    health: Health,
-    position: Vec3,
+    health: damage::Health,
+    collider: Collider,
+    body: Simulated,
+    #[bundle]
+    vehicle: vehicle::VehicleBundle,
+    progress: race::RaceProgress,
+    slot: race::GridSlot,
 }
 
-#[derive(Default)]
-struct Car {
-    crashed: bool
+impl CarBundle {
+    /// Car with the `index` color of the game resources, it waits at `translation` until it is put on the grid.
+    fn new(
+        game_resource: &resources::GameResources,
+        index: usize,
+        slot: race::GridSlot,
+        translation: Vec3,
+        name: String,
+    ) -> Self {
+        let transform = Transform {
+            translation,
+            scale: Vec3::splat(game_resource.car_scale),
+            ..Default::default()
+        };
+        CarBundle {
+            sprite: SpriteBundle {
+                material: game_resource.cars[index % game_resource.cars.len()].clone(),
+                transform,
+                ..Default::default()
+            },
+            car: Car::default(),
+            name: Name(name),
+            health: damage::Health::new(100f32),
+            collider: Collider::solid(Shape::Box { size: game_resource.car_size }, Layers::CARS),
+            body: Simulated::new(transform),
+            vehicle: vehicle::VehicleBundle::default(),
+            progress: race::RaceProgress::default(),
+            slot,
+        }
+    }
 }
 
-struct Health(usize);
-
-struct Name(String);
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+enum CarSystem {
+    Colliders,
+    Cars,
+}
 
-enum Collider {
-    Wall,
+/// Copy of a car for resolving contacts between pairs of them.
+struct CarBody {
+    entity: Entity,
+    collider: Collider,
+    size: Vec2,
+    mass: f32,
+    restitution: f32,
+    velocity: Vec2,
+    transform: Transform,
 }
 
-struct Wall;
+/// Distance between the cars waiting for the track, players wait below the origin and AI above it.
+/// Players start on the grid ahead of AI.
+const PLAYER_SPACING: f32 = 40f32;
+
+const OPPONENTS: [ai::Difficulty; 3] = [ai::Difficulty::Easy, ai::Difficulty::Medium, ai::Difficulty::Hard];
 
 fn main() {
     bevy::app::App::build()
         .add_plugins(bevy::DefaultPlugins)
+        .add_plugin(simulation::SimulationPlugin)
+        .add_plugin(actions::ActionsPlugin)
+        .add_plugin(split_screen::SplitScreenPlugin)
         .add_plugin(line::LinePlugin)
         .add_plugin(cursor::CursorPlugin)
+        .add_plugin(track::TrackPlugin)
+        .add_plugin(race::RacePlugin)
+        .add_plugin(vehicle::VehiclePlugin)
+        .add_plugin(collision::CollisionPlugin)
+        .add_plugin(boundary::BoundaryPlugin)
+        .add_plugin(respawn::RespawnPlugin)
+        .add_plugin(damage::DamagePlugin)
+        .add_plugin(ai::AiPlugin)
         .init_resource::<resources::GameResources>()
         .add_startup_system(startup.system())
-        .add_system(collider_movement.system())
-        .add_system(keyboard_control.system())
-        .add_system(car_collision_system.system())
+        .add_system(join_players.system().before(actions::ActionsPluginSystem::Update))
+        .add_system(collision_log.system())
+        .add_system_to_stage(
+            simulation::SimulationStage,
+            collider_movement.system()
+                .label(simulation::SimulationSystem::Movement)
+                .after(simulation::SimulationSystem::Begin))
+        .add_system_to_stage(
+            simulation::SimulationStage,
+            car_collision_system.system()
+                .label(CarSystem::Colliders)
+                .label(simulation::SimulationSystem::Collision)
+                .after(boundary::BoundaryPluginSystem::OffRoad)
+                .after(collision::CollisionPluginSystem::Index))
+        .add_system_to_stage(
+            simulation::SimulationStage,
+            car_contact_system.system()
+                .label(CarSystem::Cars)
+                .label(simulation::SimulationSystem::Collision)
+                .after(CarSystem::Colliders))
         .run()
 }
 
-fn keyboard_control(
-    input: Res<Input<KeyCode>>,
-    window: Res<Windows>,
-    time: Res<Time>,
-    mut cars: Query<(&Car, &mut Transform)>,
+/// Spawns a car for every player of the input config and removes the cars of players who left.
+fn join_players(
+    mut commands: Commands,
+    bindings: Res<Bindings>,
+    game_resource: Res<resources::GameResources>,
+    players: Query<(Entity, &Player)>,
 ) {
-    if input.pressed(KeyCode::R) {
-        let shift_step = 30f32;
-        let mut shift = Vec3::ZERO;
-        for (_car, mut transform) in cars.iter_mut() {
-            transform.translation = shift;
-            transform.rotation = Quat::IDENTITY;
-            shift.y += shift_step;
-        }
+    if !bindings.is_changed() {
         return;
     }
-    let primary_window = window.get_primary().unwrap();
-    let height = primary_window.height() / 5f32;
-    let vertical_speed = height * time.delta_seconds(); 
-    let mut position_shift = 0f32;
-    let mut angle = 0f32;
-    if input.pressed(KeyCode::Up) {
-        position_shift += vertical_speed;
+    let count = bindings.players.len();
+    for (car, player) in players.iter() {
+        if player.0 >= count {
+            commands.entity(car).despawn();
+        }
     }
-    if input.pressed(KeyCode::Down) {
-        position_shift -= vertical_speed;
+    for player in 0..count {
+        if players.iter().any(|(_car, joined)| joined.0 == player) {
+            continue;
+        }
+        let translation = Vec3::new(0f32, -PLAYER_SPACING * player as f32, 0f32);
+        let name = format!("Player {}", player + 1);
+        commands
+            .spawn_bundle(CarBundle::new(&game_resource, player, race::GridSlot(player), translation, name))
+            .insert(Player(player))
+            .insert(ActionState::default());
     }
-    if input.pressed(KeyCode::Right) {
-        angle -= 1f32;
+}
+
+fn car_collision_system(
+    index: Res<ColliderIndex>,
+    mut trigger_events: EventWriter<TriggerEvent>,
+    mut impact_events: EventWriter<ImpactEvent>,
+    mut cars: Query<(Entity, &mut Car, &Collider, &vehicle::Vehicle, &mut vehicle::VehicleState, &mut Simulated)>,
+    colliders: Query<(&Collider, &Simulated), Without<Car>>,
+) {
+    for (car_entity, mut car, car_collider, vehicle, mut state, mut car_body) in cars.iter_mut() {
+        let car_size = match car_collider.shape {
+            Shape::Box { size } => size,
+            _ => continue,
+        };
+        car.contacts.clear();
+        let mut triggers = Vec::new();
+        let car_aabb = Obb::from_transform(&car_body.current, car_size).aabb();
+        for entity in index.0.query(car_aabb) {
+            let (collider, collider_body) = match colliders.get(entity) {
+                Ok(collider) => collider,
+                Err(_) => continue,
+            };
+            if !car_collider.interacts(collider) {
+                continue;
+            }
+            let car_box = Obb::from_transform(&car_body.current, car_size);
+            let contact = match collider.shape.collide_box(&car_box, &collider_body.current) {
+                Some(contact) => contact,
+                None => continue,
+            };
+            if collider.trigger {
+                triggers.push(entity);
+                continue;
+            }
+            car_body.current.translation += (contact.normal * contact.depth).extend(0f32);
+            let velocity = collision::respond(
+                state.velocity, &contact, vehicle.restitution, vehicle.impact_friction);
+            let impulse = (velocity - state.velocity).length() * vehicle.mass;
+            if impulse > 0f32 {
+                impact_events.send(ImpactEvent { car: car_entity, impulse });
+            }
+            state.velocity = velocity;
+            car.contacts.push(contact);
+        }
+        for trigger in triggers.iter().filter(|trigger| !car.triggers.contains(trigger)) {
+            trigger_events.send(TriggerEvent::Entered { car: car_entity, trigger: *trigger });
+        }
+        for trigger in car.triggers.iter().filter(|trigger| !triggers.contains(trigger)) {
+            trigger_events.send(TriggerEvent::Exited { car: car_entity, trigger: *trigger });
+        }
+        car.triggers = triggers;
     }
-    if input.pressed(KeyCode::Left) {
-        angle += 1f32;
+}
+
+fn car_contact_system(
+    mut contact_events: EventWriter<CarContactEvent>,
+    mut cars: Query<(Entity, &Collider, &vehicle::Vehicle, &mut vehicle::VehicleState, &mut Simulated), With<Car>>,
+) {
+    let mut bodies: Vec<CarBody> = cars.iter_mut()
+        .filter_map(|(entity, collider, vehicle, state, body)| match collider.shape {
+            Shape::Box { size } => Some(CarBody {
+                entity,
+                collider: collider.clone(),
+                size,
+                mass: vehicle.mass,
+                restitution: vehicle.restitution,
+                velocity: state.velocity,
+                transform: body.current,
+            }),
+            _ => None,
+        })
+        .collect();
+    // the same order on every run keeps the simulation deterministic.
+    bodies.sort_by_key(|body| body.entity);
+    // the collider index was built before walls and road edges pushed the cars,
+    // so the cars are indexed again where they are now.
+    let mut index = SpatialHash::new(collision::CELL_SIZE);
+    for (car, body) in bodies.iter().enumerate() {
+        index.insert(car, Obb::from_transform(&body.transform, body.size).aabb());
     }
-    for (car, mut transform) in cars.iter_mut() {
-        if car.crashed {
-            transform.translation = Vec3::ZERO;
-            transform.rotation = Quat::IDENTITY;
+    for first in 0..bodies.len() {
+        let aabb = Obb::from_transform(&bodies[first].transform, bodies[first].size).aabb();
+        for second in index.query(aabb) {
+            if second <= first {
+                continue;
+            }
+            let (a, b) = (&bodies[first], &bodies[second]);
+            if !a.collider.interacts(&b.collider) {
+                continue;
+            }
+            let contact = match collision::collide_boxes(
+                &Obb::from_transform(&a.transform, a.size),
+                &Obb::from_transform(&b.transform, b.size))
+            {
+                Some(contact) => contact,
+                None => continue,
+            };
+            let (inverse_a, inverse_b) = (1f32 / a.mass, 1f32 / b.mass);
+            let inverse_sum = inverse_a + inverse_b;
+            let correction = contact.normal * contact.depth / inverse_sum;
+            let closing = (a.velocity - b.velocity).dot(contact.normal);
+            let impulse = if closing < 0f32 {
+                -(1f32 + (a.restitution + b.restitution) / 2f32) * closing / inverse_sum
+            } else {
+                0f32
+            };
+            bodies[first].transform.translation += (correction * inverse_a).extend(0f32);
+            bodies[first].velocity += contact.normal * impulse * inverse_a;
+            bodies[second].transform.translation -= (correction * inverse_b).extend(0f32);
+            bodies[second].velocity -= contact.normal * impulse * inverse_b;
+            contact_events.send(CarContactEvent {
+                first: bodies[first].entity,
+                second: bodies[second].entity,
+                normal: contact.normal,
+                impulse,
+            });
         }
-        else {
-            let (axis, mut current_angle) = transform.rotation.to_axis_angle();
-            //println!("Angle {:#?}", (axis, current_angle));
-            current_angle = 2f32 * std::f32::consts::PI + current_angle * axis.z;
-            transform.translation += Vec3::new(
-                position_shift * (current_angle.cos()),
-                position_shift * current_angle.sin(),
-                0f32);
-            transform.rotation *= Quat::from_rotation_z(angle / 20f32); 
+    }
+    for body in bodies {
+        if let Ok((_entity, _collider, _vehicle, mut state, mut simulated)) = cars.get_mut(body.entity) {
+            state.velocity = body.velocity;
+            simulated.current = body.transform;
         }
     }
 }
 
-fn car_collision_system(
-    mut cars: Query<(&mut Car, &Transform)>,
-    colliders: Query<(&Collider, &Transform)>,
+fn collision_log(
+    mut trigger_events: EventReader<TriggerEvent>,
+    mut contact_events: EventReader<CarContactEvent>,
 ) {
-    let car_size = Vec2::new(50f32, 30f32);
-    for (mut car, car_transform) in cars.iter_mut() {
-        let mut collision_happened = false;
-        for (_collider, collider_transform) in colliders.iter() {
-            let collision = collide(
-                car_transform.translation,
-                car_size,
-                collider_transform.translation,
-                collider_transform.scale.truncate() * Vec2::ONE);
-            if let Some(_collision) = collision {
-                collision_happened = true;
-                break;
-            } 
+    for event in trigger_events.iter() {
+        match event {
+            TriggerEvent::Entered { car, trigger } => info!("Car {:?} entered {:?}", car, trigger),
+            TriggerEvent::Exited { car, trigger } => info!("Car {:?} left {:?}", car, trigger),
         }
-        car.crashed = collision_happened;
+    }
+    // resting contacts only push cars apart, impacts exchange momentum.
+    for event in contact_events.iter().filter(|event| event.impulse > 0f32) {
+        info!("Car {:?} hit {:?} along {:?} with impulse {:.1}", event.first, event.second, event.normal, event.impulse);
     }
 }
 
 fn collider_movement(
-    mut colliders: Query<(&Wall, &mut Transform, Without<OrthographicProjection>)>,
-    camera: Query<&OrthographicProjection>
+    mut colliders: Query<(&Wall, &mut Simulated)>,
+    windows: Res<Windows>,
 ) {
-    let projection = camera.single().unwrap();
+    // walls wrap around the window centered on the origin, whatever the cameras are.
+    let window = windows.get_primary().unwrap();
+    let half_size = vec2(window.width(), window.height()) / 2f32;
     let shift = 2.5f32;
-    for (_collider, mut transform, _) in colliders.iter_mut() {
-        let mut next_x_position = transform.translation.x + shift;
-        if next_x_position > projection.right {
-            next_x_position = projection.left;
+    for (_collider, mut body) in colliders.iter_mut() {
+        let mut wrapped = false;
+        let mut next_x_position = body.current.translation.x + shift;
+        if next_x_position > half_size.x {
+            next_x_position = -half_size.x;
+            wrapped = true;
         }
-        let mut next_y_position = transform.translation.y + shift;
-        if next_y_position > projection.top {
-            next_y_position = projection.bottom;
+        let mut next_y_position = body.current.translation.y + shift;
+        if next_y_position > half_size.y {
+            next_y_position = -half_size.y;
+            wrapped = true;
         }
+        let mut transform = body.current;
         transform.translation = Vec3::new(
            next_x_position,
            next_y_position,
            0f32
         );
+        if wrapped {
+            body.teleport(transform);
+        } else {
+            body.current = transform;
+        }
     }
 }
 
@@ -169,7 +376,6 @@ fn startup(
     let window = windows.get_primary().unwrap();
     let width = window.width();
 
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d());
     commands.spawn_bundle(SpriteBundle {
         material: materials.add(ColorMaterial::color(Color::rgb(1.0, 0.1, 0.5))),
         sprite: Sprite::new(vec2(1.0, 1.0)),
@@ -181,44 +387,24 @@ fn startup(
         ..Default::default()
     });
 
-    let sections = road::generate_sections();
-    let sections_line = road::sections_into_line(&sections);
-    let sections_normals = line::line_to_normals(&line::line_to_points(&sections_line));
-    let road_mesh = road::generate_road(&sections);
-    commands.spawn_bundle(LineBundle::from_line(sections_line, Color::BLUE));
-    commands.spawn_bundle(LineBundle::from_points(sections_normals, Color::RED));
-    commands.spawn_bundle(LineBundle::from_mesh(&road_mesh));
-    commands
-        .spawn_bundle(SpriteBundle {
-            mesh:  meshes.add(road_mesh),
-            material: materials.add(ColorMaterial::color(Color::rgb(0.3, 0.3, 0.5))),
-            sprite: Sprite::new(vec2(1.0, 1.0)),
-            transform: Transform {
-                translation: vec3(-600.0, 000.0, 0.0),
-                ..Default::default()
-            },
-            ..Default::default()
-        }).insert(bevy::render::wireframe::Wireframe);
+    let pickup_transform = Transform::from_translation(Vec3::new(-100.0, 0.0, 0.0));
     commands.spawn_bundle(SpriteBundle {
         mesh: meshes.add(shape::Cube { size: 10f32 }.into()),
         material: materials.add(ColorMaterial::color(Color::rgb(1.0, 1.0, 0.0))),
         sprite: Sprite::new(vec2(1f32, 1f32)),
-        transform: Transform {
-            translation: Vec3::new(-100.0, 0.0, 0.0),
-            ..Default::default()
-        },
+        transform: pickup_transform,
         ..Default::default()
-    });
-    commands
-        .spawn_bundle(SpriteBundle {
-            material: game_resource.car.clone(),
-            transform: Transform {
-                translation: Vec3::new(0.0, 0.0, 0.0),
-                scale: Vec3::splat(game_resource.car_scale),
-                ..Default::default()
-            },
-            ..Default::default()
-        }).insert(Car::default());
+    })
+        .insert(Simulated::new(pickup_transform))
+        .insert(Collider::trigger(Shape::Circle { radius: 5f32 }, Layers::PICKUPS));
+    for (opponent, difficulty) in OPPONENTS.iter().enumerate() {
+        let translation = Vec3::new(0f32, PLAYER_SPACING * (opponent + 1) as f32, 0f32);
+        let name = format!("AI {} ({:?})", opponent + 1, difficulty);
+        let slot = race::GridSlot(actions::MAX_PLAYERS + opponent);
+        commands
+            .spawn_bundle(CarBundle::new(&game_resource, opponent + 1, slot, translation, name))
+            .insert(ai::AiDriver::new(*difficulty));
+    }
 
     commands.spawn_bundle(SpriteBundle {
         material: materials.add(Color::rgb(0.2, 1.0, 0.2).into()),
@@ -226,22 +412,22 @@ fn startup(
             size: Vec2::new(width / 4f32, 10f32),
             ..Default::default()
         },
-        transform: Transform {
-            translation: Vec3::new(0f32, 100f32, 0f32),
-            ..Default::default()
-        },
+        transform: Transform::from_translation(Vec3::new(0f32, 100f32, 0f32)),
         ..Default::default()
-    }).insert(Collider::Wall).insert(Wall);
+    })
+        .insert(Simulated::new(Transform::from_translation(Vec3::new(0f32, 100f32, 0f32))))
+        .insert(Collider::solid(Shape::Box { size: Vec2::new(width / 4f32, 10f32) }, Layers::WALLS))
+        .insert(Wall);
     commands.spawn_bundle(SpriteBundle {
         material: materials.add(Color::rgb(0.2, 1.0, 0.2).into()),
         sprite: Sprite {
             size: Vec2::new(1f32, 1f32),
             ..Default::default()
         },
-        transform: Transform {
-            translation: Vec3::new(0f32, 200f32, 0f32),
-            ..Default::default()
-        },
+        transform: Transform::from_translation(Vec3::new(0f32, 200f32, 0f32)),
         ..Default::default()
-    }).insert(Collider::Wall).insert(Wall);
+    })
+        .insert(Simulated::new(Transform::from_translation(Vec3::new(0f32, 200f32, 0f32))))
+        .insert(Collider::solid(Shape::Box { size: Vec2::ONE }, Layers::WALLS))
+        .insert(Wall);
 }
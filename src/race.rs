@@ -0,0 +1,612 @@
+use std::cmp::Ordering;
+use bevy::{
+    app::{AppBuilder, EventReader, EventWriter, Plugin},
+    ecs::{
+        entity::Entity,
+        query::{Added, Without},
+        schedule::SystemLabel,
+        system::{IntoSystem, Query, Res},
+    },
+    log::{info, warn},
+    math::{Quat, Vec3},
+    prelude::ParallelSystemDescriptorCoercion,
+    transform::components::Transform,
+};
+use crate::damage::Eliminated;
+use crate::road::{self, Edge, Pivot, RoadMesh};
+use crate::simulation::{Simulated, SimulationStage, SimulationSystem, SimulationTime};
+use crate::track::{Centerline, StartFinishLine};
+use crate::vehicle::VehicleState;
+
+/// Distance between cars across the start line.
+const GRID_SPACING: f32 = 30f32;
+/// Distance between rows of the starting grid along the road, the first row is that far from the start line.
+const GRID_ROW_LENGTH: f32 = 50f32;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+pub enum RacePluginSystem {
+    LineUp,
+    Progress,
+    Log,
+}
+
+pub struct RacePlugin;
+
+impl Plugin for RacePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_event::<RaceEvent>()
+            .add_system_to_stage(
+                SimulationStage,
+                line_up.system()
+                    .label(RacePluginSystem::LineUp)
+                    .after(SimulationSystem::Begin)
+                    .before(SimulationSystem::Movement))
+            .add_system_to_stage(
+                SimulationStage,
+                race_progress.system()
+                    .label(RacePluginSystem::Progress)
+                    .label(SimulationSystem::Race)
+                    .after(SimulationSystem::Collision))
+            .add_system(race_log.system().label(RacePluginSystem::Log));
+    }
+}
+
+/// Line across the road in world coordinates, it has to be crossed along `direction`.
+#[derive(Debug, Clone, Copy)]
+pub struct Gate {
+    pub left: Vec3,
+    pub right: Vec3,
+    pub direction: Vec3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crossing {
+    Forward,
+    Backward,
+}
+
+impl Gate {
+    pub fn across(transform: &Transform, position: Vec3, direction: Vec3, width: road::Width) -> Self {
+        let edge = Edge::across(position, direction, width);
+        Gate {
+            left: transform.mul_vec3(edge.left),
+            right: transform.mul_vec3(edge.right),
+            direction: transform.rotation.mul_vec3(direction).normalize(),
+        }
+    }
+
+    pub fn at_pivot(transform: &Transform, pivot: &Pivot) -> Self {
+        Gate::across(transform, pivot.position(), pivot.direction(), pivot.width())
+    }
+
+    /// Places for cars on the gate about `spacing` apart, from its middle outwards.
+    pub fn row(&self, spacing: f32) -> Vec<Vec3> {
+        let across = self.right - self.left;
+        let columns = ((across.length() / spacing).floor() as usize).max(1);
+        let mut ratios: Vec<f32> = (0..columns).map(|column| (column as f32 + 0.5) / columns as f32).collect();
+        ratios.sort_by(|a, b| (a - 0.5).abs().partial_cmp(&(b - 0.5).abs()).unwrap_or(Ordering::Equal));
+        ratios.into_iter().map(|ratio| self.left + across * ratio).collect()
+    }
+
+    /// Whether the movement from `from` to `to` goes through the gate.
+    pub fn crossing(&self, from: Vec3, to: Vec3) -> Option<Crossing> {
+        let movement = to - from;
+        let (t, u) = road::intersect(from, movement, self.left, self.right - self.left)?;
+        if !(0f32..=1f32).contains(&t) || !(0f32..=1f32).contains(&u) {
+            return None;
+        }
+        if movement.dot(self.direction) >= 0f32 {
+            Some(Crossing::Forward)
+        } else {
+            Some(Crossing::Backward)
+        }
+    }
+}
+
+/// Boundary between sections of the road. Checkpoints have to be passed
+/// in the order of `index`, the last one finishes the lap.
+pub struct Checkpoint {
+    pub index: usize,
+    pub gate: Gate,
+}
+
+/// Places for cars on the road with the direction of the road there, in rows of places
+/// `spacing` apart across the road. Rows are `row_length` apart along the centerline, they go
+/// back from `first` behind `distance`, then ahead of it where an open road begins behind it.
+/// Places off the road are left out.
+pub fn road_grid<'a>(
+    road: &'a RoadMesh,
+    road_transform: &Transform,
+    centerline: &'a Centerline,
+    distance: f32,
+    first: f32,
+    spacing: f32,
+    row_length: f32,
+) -> impl Iterator<Item = (Vec3, Vec3)> + 'a {
+    let (length, closed) = (centerline.length(), centerline.closed);
+    // rows around a whole loop would lie on the first ones again.
+    let rows = ((length / row_length).floor() as usize).max(1);
+    let back = (0..rows)
+        .map(move |row| distance - first - row as f32 * row_length)
+        .take_while(move |distance| closed || *distance >= 0f32);
+    let ahead = (1..)
+        .map(move |row| distance + row as f32 * row_length)
+        .take_while(move |distance| !closed && *distance <= length);
+    let to_road = road_transform.compute_matrix().inverse();
+    back.chain(ahead)
+        .filter_map(move |distance| centerline.at(distance))
+        .flat_map(move |(position, direction, width)| {
+            let row = Edge::across(position, direction, width);
+            let gate = Gate { left: row.left, right: row.right, direction };
+            gate.row(spacing).into_iter().map(move |place| (place, direction))
+        })
+        .filter(move |(place, _direction)| road.triangle_at(to_road.transform_point3(*place)).is_some())
+}
+
+/// Place of a car on the starting grid, cars with lower ones start further ahead.
+/// Slots do not have to follow each other, the grid is filled in their order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GridSlot(pub usize);
+
+/// Race state of a car, the lap starts when the car crosses the start line.
+#[derive(Debug, Default)]
+pub struct RaceProgress {
+    pub laps: usize,
+    pub next_checkpoint: usize,
+    pub lap_start: Option<f64>,
+    /// Times of the passed checkpoints since the start of the current lap.
+    pub splits: Vec<f64>,
+    pub best_lap: Option<f64>,
+    /// Splits of the best lap, new splits are compared to them.
+    pub best_splits: Vec<f64>,
+    pub wrong_way: bool,
+    previous_position: Option<Vec3>,
+}
+
+impl RaceProgress {
+    /// Index of the last passed checkpoint of the current lap.
+    pub fn last_checkpoint(&self) -> Option<usize> {
+        self.next_checkpoint.checked_sub(1).filter(|_| self.lap_start.is_some())
+    }
+
+    /// The car was moved without driving, so no gates were crossed.
+    pub fn teleported(&mut self) {
+        self.previous_position = None;
+    }
+
+    /// Cars put past the start line of an open track can not cross it, their lap starts where they are.
+    pub fn standing_start(&mut self, now: f64) {
+        if self.lap_start.is_none() {
+            self.start_lap(now);
+        }
+    }
+
+    fn restart(&mut self) {
+        *self = RaceProgress::default();
+    }
+
+    fn start_lap(&mut self, now: f64) {
+        self.lap_start = Some(now);
+        self.next_checkpoint = 0;
+        self.splits.clear();
+    }
+
+    /// Moves the car to `position` through the gates between it and the last position,
+    /// `checkpoints` are ordered by their index.
+    fn advance(
+        &mut self,
+        car: Entity,
+        position: Vec3,
+        checkpoints: &[&Checkpoint],
+        start_lines: &[Gate],
+        now: f64,
+    ) -> Vec<RaceEvent> {
+        let mut events = Vec::new();
+        let previous = match self.previous_position.replace(position) {
+            Some(previous) => previous,
+            None => return events,
+        };
+        for checkpoint in checkpoints.iter() {
+            match checkpoint.gate.crossing(previous, position) {
+                Some(Crossing::Forward) => {
+                    self.wrong_way = false;
+                    self.pass_checkpoint(car, checkpoint, checkpoints.len(), now, &mut events);
+                },
+                Some(Crossing::Backward) => self.turn_back(car, &mut events),
+                None => {},
+            }
+        }
+        // on a closed track the finish is the start line, so the next lap starts right away.
+        for start_line in start_lines.iter() {
+            match start_line.crossing(previous, position) {
+                Some(Crossing::Forward) if self.lap_start.is_none() => self.start_lap(now),
+                Some(Crossing::Backward) => self.turn_back(car, &mut events),
+                _ => {},
+            }
+        }
+        events
+    }
+
+    fn pass_checkpoint(
+        &mut self,
+        car: Entity,
+        checkpoint: &Checkpoint,
+        count: usize,
+        now: f64,
+        events: &mut Vec<RaceEvent>,
+    ) {
+        let lap_start = match self.lap_start {
+            Some(lap_start) => lap_start,
+            None => return,
+        };
+        if checkpoint.index < self.next_checkpoint {
+            return;
+        }
+        if checkpoint.index > self.next_checkpoint {
+            events.push(RaceEvent::CheckpointMissed {
+                car,
+                expected: self.next_checkpoint,
+                reached: checkpoint.index,
+            });
+            return;
+        }
+        let split = now - lap_start;
+        let delta = self.best_splits.get(checkpoint.index).map(|best| split - best);
+        self.splits.push(split);
+        self.next_checkpoint += 1;
+        events.push(RaceEvent::CheckpointPassed { car, checkpoint: checkpoint.index, split, delta });
+        if self.next_checkpoint < count {
+            return;
+        }
+        let best = match self.best_lap {
+            Some(best_lap) => split < best_lap,
+            None => true,
+        };
+        if best {
+            self.best_lap = Some(split);
+            self.best_splits = self.splits.clone();
+        }
+        self.laps += 1;
+        self.lap_start = None;
+        events.push(RaceEvent::LapCompleted { car, lap: self.laps, time: split, best });
+    }
+
+    fn turn_back(&mut self, car: Entity, events: &mut Vec<RaceEvent>) {
+        if !self.wrong_way {
+            self.wrong_way = true;
+            events.push(RaceEvent::WrongWay { car });
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RaceEvent {
+    /// `delta` is the time against the same checkpoint of the best lap, once there is one.
+    CheckpointPassed { car: Entity, checkpoint: usize, split: f64, delta: Option<f64> },
+    /// `reached` checkpoint was crossed before the `expected` one, it is not counted.
+    CheckpointMissed { car: Entity, expected: usize, reached: usize },
+    LapCompleted { car: Entity, lap: usize, time: f64, best: bool },
+    WrongWay { car: Entity },
+}
+
+fn race_progress(
+    time: Res<SimulationTime>,
+    mut events: EventWriter<RaceEvent>,
+    mut cars: Query<(Entity, &Simulated, &mut RaceProgress), Without<Eliminated>>,
+    checkpoints: Query<&Checkpoint>,
+    start_lines: Query<&StartFinishLine>,
+) {
+    let now = time.seconds();
+    let mut ordered: Vec<&Checkpoint> = checkpoints.iter().collect();
+    ordered.sort_by_key(|checkpoint| checkpoint.index);
+    let start_gates: Vec<Gate> = start_lines.iter().map(|start_line| start_line.gate).collect();
+    for (car, body, mut progress) in cars.iter_mut() {
+        for event in progress.advance(car, body.current.translation, &ordered, &start_gates, now) {
+            events.send(event);
+        }
+    }
+}
+
+/// Puts every car on the grid of a new track and restarts their race,
+/// cars joining later take their own places only.
+/// The grid is behind the start line of a closed track and ahead of it on an open one,
+/// where there is no road behind the line.
+fn line_up(
+    time: Res<SimulationTime>,
+    roads: Query<(&RoadMesh, &Centerline, &Transform)>,
+    new_start_lines: Query<&StartFinishLine, Added<StartFinishLine>>,
+    new_cars: Query<&GridSlot, Added<GridSlot>>,
+    mut cars: Query<(Entity, &GridSlot, &mut Simulated, &mut VehicleState, Option<&mut RaceProgress>)>,
+) {
+    let (road, centerline, road_transform) = match roads.single() {
+        Ok(road) => road,
+        Err(_) => return,
+    };
+    let everyone = new_start_lines.iter().next().is_some();
+    if !everyone && new_cars.iter().next().is_none() {
+        return;
+    }
+    let mut slots: Vec<GridSlot> = cars.iter_mut().map(|(_car, slot, ..)| *slot).collect();
+    slots.sort_unstable();
+    let places: Vec<(Vec3, Vec3)> =
+        road_grid(road, road_transform, centerline, 0f32, GRID_ROW_LENGTH, GRID_SPACING, GRID_ROW_LENGTH)
+            .take(slots.len())
+            .collect();
+    for (car, slot, mut body, mut state, mut progress) in cars.iter_mut() {
+        if !everyone && new_cars.get(car).is_err() {
+            continue;
+        }
+        if let (true, Some(progress)) = (everyone, progress.as_mut()) {
+            // checkpoints of a new track do not match the old ones.
+            progress.restart();
+        }
+        let (place, direction) = match slots.binary_search(slot).ok().and_then(|index| places.get(index)) {
+            Some(place) => *place,
+            None => continue,
+        };
+        let transform = Transform {
+            translation: place.truncate().extend(body.current.translation.z),
+            rotation: Quat::from_rotation_z(direction.y.atan2(direction.x)),
+            scale: body.current.scale,
+        };
+        body.teleport(transform);
+        *state = VehicleState::default();
+        if let Some(mut progress) = progress {
+            progress.teleported();
+            if !centerline.closed {
+                progress.standing_start(time.seconds());
+            }
+        }
+    }
+}
+
+fn race_log(mut events: EventReader<RaceEvent>) {
+    for event in events.iter() {
+        match event {
+            RaceEvent::CheckpointPassed { car, checkpoint, split, delta } => match delta {
+                Some(delta) => info!("Car {:?} passed checkpoint {} at {:.3}s ({:+.3}s)", car, checkpoint, split, delta),
+                None => info!("Car {:?} passed checkpoint {} at {:.3}s", car, checkpoint, split),
+            },
+            RaceEvent::CheckpointMissed { car, expected, reached } =>
+                warn!("Car {:?} reached checkpoint {} skipping {}", car, reached, expected),
+            RaceEvent::LapCompleted { car, lap, time, best } =>
+                info!("Car {:?} completed lap {} in {:.3}s{}", car, lap, time, if *best { ", best lap" } else { "" }),
+            RaceEvent::WrongWay { car } =>
+                warn!("Car {:?} drives the wrong way", car),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::vec3;
+    use super::*;
+
+    #[test]
+    fn rows_fill_the_gate_from_the_middle() {
+        let gate = Gate { left: vec3(0f32, 30f32, 0f32), right: vec3(0f32, -30f32, 0f32), direction: Vec3::X };
+        assert_eq!(gate.row(25f32), vec![vec3(0f32, 15f32, 0f32), vec3(0f32, -15f32, 0f32)]);
+        let narrow = Gate { left: Vec3::ZERO, right: Vec3::ZERO, direction: Vec3::Y };
+        assert_eq!(narrow.row(25f32), vec![Vec3::ZERO]);
+    }
+
+    /// Road from the origin made of `sides`, in segments of 50 and 30 to both borders.
+    fn road(sides: &[Vec3], closed: bool) -> (RoadMesh, Centerline) {
+        let width = road::Width::symmetric(30f32);
+        let sections = vec![sides.iter().flat_map(|side| vec![(width, *side / 3f32); 3]).collect::<Vec<_>>()];
+        let start = Pivot::start_of(&sections);
+        let (mesh, _pivots) =
+            road::generate_road(start, &sections, road::JoinStyle::default(), road::Taper::Linear, closed);
+        let points = road::sections_into_line(start.position(), &sections);
+        let centerline = Centerline { widths: vec![width; points.len()], points, closed };
+        (mesh, centerline)
+    }
+
+    /// Sides of a square of 150 turning left.
+    fn square() -> [Vec3; 4] {
+        [vec3(150f32, 0f32, 0f32), vec3(0f32, 150f32, 0f32), vec3(-150f32, 0f32, 0f32), vec3(0f32, -150f32, 0f32)]
+    }
+
+    #[test]
+    fn grid_is_behind_the_start_of_a_loop_and_ahead_of_an_open_road() {
+        let (mesh, centerline) = road(&square(), true);
+        let places: Vec<(Vec3, Vec3)> = road_grid(&mesh, &Transform::identity(), &centerline, 0f32, 50f32, 30f32, 50f32)
+            .take(4)
+            .collect();
+        assert_eq!(places, vec![
+            (vec3(15f32, 50f32, 0f32), -Vec3::Y),
+            (vec3(-15f32, 50f32, 0f32), -Vec3::Y),
+            (vec3(15f32, 100f32, 0f32), -Vec3::Y),
+            (vec3(-15f32, 100f32, 0f32), -Vec3::Y),
+        ]);
+
+        // the road ends at the corner, so only three rows are left on the road ahead.
+        let (mesh, centerline) = road(&square()[..1], false);
+        let places: Vec<(Vec3, Vec3)> = road_grid(&mesh, &Transform::identity(), &centerline, 0f32, 50f32, 30f32, 50f32)
+            .collect();
+        assert_eq!(places.len(), 6);
+        assert_eq!(places[..2], [(vec3(50f32, 15f32, 0f32), Vec3::X), (vec3(50f32, -15f32, 0f32), Vec3::X)]);
+        assert!(places.iter().all(|(place, _direction)| place.x > 0f32 && place.x <= 150f32));
+
+        // respawned cars go back from their checkpoint, then ahead of it at the beginning of the road.
+        let moved = Transform::from_xyz(-600f32, 0f32, 0f32);
+        let line = Centerline { points: centerline.points.iter().map(|point| moved.mul_vec3(*point)).collect(), ..centerline };
+        let rows: Vec<f32> = road_grid(&mesh, &moved, &line, 50f32, 0f32, 30f32, 50f32)
+            .step_by(2)
+            .map(|(place, _direction)| place.x + 600f32)
+            .collect();
+        assert_eq!(rows, vec![50f32, 0f32, 100f32, 150f32]);
+    }
+
+    #[test]
+    fn cars_line_up_ahead_of_an_open_start_with_their_lap_started() {
+        use bevy::ecs::{schedule::{Stage, SystemStage}, world::World};
+
+        let mut world = World::default();
+        world.insert_resource(SimulationTime { tick: 60 });
+        let (mesh, centerline) = road(&square()[..1], false);
+        world.spawn().insert_bundle((mesh, centerline, Transform::identity()));
+        world.spawn().insert(StartFinishLine { gate: gate(0f32) });
+        let cars: Vec<Entity> = (0..3)
+            .map(|slot| {
+                let waiting = Simulated::new(Transform::from_xyz(0f32, -500f32, 0f32));
+                world.spawn()
+                    .insert_bundle((GridSlot(slot), waiting, VehicleState::default(), RaceProgress::default()))
+                    .id()
+            })
+            .collect();
+        SystemStage::single(line_up.system()).run(&mut world);
+
+        let places: Vec<Vec3> = cars.iter()
+            .map(|car| world.get::<Simulated>(*car).unwrap().current.translation)
+            .collect();
+        assert_eq!(places, vec![vec3(50f32, 15f32, 0f32), vec3(50f32, -15f32, 0f32), vec3(100f32, 15f32, 0f32)]);
+        assert!(cars.iter().all(|car| world.get::<RaceProgress>(*car).unwrap().lap_start == Some(1f64)));
+    }
+
+    /// Gate across a road along X at `x`, 30 to both sides.
+    fn gate(x: f32) -> Gate {
+        Gate { left: vec3(x, 30f32, 0f32), right: vec3(x, -30f32, 0f32), direction: Vec3::X }
+    }
+
+    #[test]
+    fn gates_are_crossed_between_their_ends_in_either_direction() {
+        let gate = gate(100f32);
+        assert_eq!(gate.crossing(vec3(90f32, 0f32, 0f32), vec3(110f32, 10f32, 0f32)), Some(Crossing::Forward));
+        assert_eq!(gate.crossing(vec3(110f32, -20f32, 0f32), vec3(90f32, -20f32, 0f32)), Some(Crossing::Backward));
+        assert_eq!(gate.crossing(vec3(90f32, 40f32, 0f32), vec3(110f32, 40f32, 0f32)), None);
+        assert_eq!(gate.crossing(vec3(80f32, 0f32, 0f32), vec3(95f32, 0f32, 0f32)), None);
+    }
+
+    /// Start line at 0, checkpoints at 100 and at the finish at 200.
+    struct Race {
+        car: Entity,
+        progress: RaceProgress,
+        checkpoints: Vec<Checkpoint>,
+        start_lines: Vec<Gate>,
+    }
+
+    impl Race {
+        fn new() -> Self {
+            let checkpoints = vec![
+                Checkpoint { index: 0, gate: gate(100f32) },
+                Checkpoint { index: 1, gate: gate(200f32) },
+            ];
+            Race { car: Entity::new(1), progress: RaceProgress::default(), checkpoints, start_lines: vec![gate(0f32)] }
+        }
+
+        fn drive(&mut self, x: f32, now: f64) -> Vec<RaceEvent> {
+            let checkpoints: Vec<&Checkpoint> = self.checkpoints.iter().collect();
+            self.progress.advance(self.car, vec3(x, 0f32, 0f32), &checkpoints, &self.start_lines, now)
+        }
+    }
+
+    #[test]
+    fn laps_are_timed_from_the_start_line_through_every_checkpoint() {
+        let mut race = Race::new();
+        let car = race.car;
+        assert_eq!(race.drive(-10f32, 0f64), vec![]);
+        assert_eq!(race.drive(10f32, 1f64), vec![]);
+        assert_eq!(race.drive(110f32, 3f64), vec![
+            RaceEvent::CheckpointPassed { car, checkpoint: 0, split: 2f64, delta: None },
+        ]);
+        assert_eq!(race.progress.last_checkpoint(), Some(0));
+        assert_eq!(race.drive(210f32, 6f64), vec![
+            RaceEvent::CheckpointPassed { car, checkpoint: 1, split: 5f64, delta: None },
+            RaceEvent::LapCompleted { car, lap: 1, time: 5f64, best: true },
+        ]);
+        assert_eq!((race.progress.best_lap, race.progress.best_splits.clone()), (Some(5f64), vec![2f64, 5f64]));
+
+        // the next lap is compared to the best one.
+        race.progress.teleported();
+        race.drive(-10f32, 10f64);
+        race.drive(10f32, 10f64);
+        assert_eq!(race.drive(110f32, 11f64), vec![
+            RaceEvent::CheckpointPassed { car, checkpoint: 0, split: 1f64, delta: Some(-1f64) },
+        ]);
+        assert_eq!(race.drive(210f32, 17f64), vec![
+            RaceEvent::CheckpointPassed { car, checkpoint: 1, split: 7f64, delta: Some(2f64) },
+            RaceEvent::LapCompleted { car, lap: 2, time: 7f64, best: false },
+        ]);
+        assert_eq!((race.progress.laps, race.progress.best_lap), (2, Some(5f64)));
+        assert_eq!(race.progress.best_splits, vec![2f64, 5f64]);
+    }
+
+    #[test]
+    fn checkpoints_only_count_after_the_start_line() {
+        let mut race = Race::new();
+        race.drive(90f32, 0f64);
+        assert_eq!(race.drive(110f32, 1f64), vec![]);
+        assert_eq!(race.progress.last_checkpoint(), None);
+    }
+
+    #[test]
+    fn skipped_checkpoints_do_not_count() {
+        let mut race = Race::new();
+        let car = race.car;
+        race.drive(-10f32, 0f64);
+        race.drive(10f32, 1f64);
+        // around the first checkpoint.
+        race.progress.teleported();
+        race.drive(150f32, 2f64);
+        assert_eq!(race.drive(210f32, 3f64), vec![
+            RaceEvent::CheckpointMissed { car, expected: 0, reached: 1 },
+        ]);
+        assert_eq!((race.progress.laps, race.progress.next_checkpoint), (0, 0));
+    }
+
+    #[test]
+    fn driving_back_through_gates_is_the_wrong_way_once() {
+        let mut race = Race::new();
+        let car = race.car;
+        race.drive(-10f32, 0f64);
+        race.drive(10f32, 1f64);
+        race.drive(110f32, 2f64);
+        assert_eq!(race.drive(90f32, 3f64), vec![RaceEvent::WrongWay { car }]);
+        assert_eq!(race.drive(-10f32, 4f64), vec![]);
+        assert!(race.progress.wrong_way);
+        race.drive(10f32, 5f64);
+        assert!(race.progress.wrong_way);
+        // passing the checkpoint again does not count it twice.
+        assert_eq!(race.drive(110f32, 6f64), vec![]);
+        assert!(!race.progress.wrong_way);
+        assert_eq!(race.progress.splits, vec![1f64]);
+    }
+
+    #[test]
+    fn eliminated_cars_make_no_progress() {
+        use bevy::app::Events;
+        use bevy::ecs::{schedule::{Stage, SystemStage}, world::World};
+        use crate::damage::Eliminated;
+
+        let mut world = World::default();
+        world.insert_resource(SimulationTime::default());
+        world.insert_resource(Events::<RaceEvent>::default());
+        world.spawn().insert(StartFinishLine { gate: gate(0f32) });
+        world.spawn().insert(Checkpoint { index: 0, gate: gate(100f32) });
+        let at = |x: f32| Simulated::new(Transform::from_xyz(x, 0f32, 0f32));
+        let racing = world.spawn().insert_bundle((at(-10f32), RaceProgress::default())).id();
+        let wreck = world.spawn().insert_bundle((at(-10f32), RaceProgress::default(), Eliminated)).id();
+        let mut stage = SystemStage::single(race_progress.system());
+        for x in [-10f32, 10f32, 110f32].iter() {
+            for car in [racing, wreck].iter() {
+                world.get_mut::<Simulated>(*car).unwrap().current.translation.x = *x;
+            }
+            world.get_resource_mut::<SimulationTime>().unwrap().tick += 60;
+            stage.run(&mut world);
+        }
+
+        let events = world.get_resource::<Events<RaceEvent>>().unwrap();
+        let cars: Vec<Entity> = events.get_reader().iter(events)
+            .map(|event| match event {
+                RaceEvent::CheckpointPassed { car, .. }
+                | RaceEvent::CheckpointMissed { car, .. }
+                | RaceEvent::LapCompleted { car, .. }
+                | RaceEvent::WrongWay { car } => *car,
+            })
+            .collect();
+        assert_eq!(cars, vec![racing, racing]);
+        assert_eq!(world.get::<RaceProgress>(wreck).unwrap().lap_start, None);
+    }
+}
@@ -1,12 +1,22 @@
 use bevy::{
     ecs::world::{World, FromWorld},
     asset::{Handle, AssetServer, Assets},
+    math::{vec2, Vec2},
+    render::color::Color,
     sprite::ColorMaterial,
 };
+use crate::actions::InputConfig;
+use crate::track::TrackDescription;
 
 pub struct GameResources {
-    pub car: Handle<ColorMaterial>,
+    /// Car of every player, they repeat when there are more players.
+    pub cars: Vec<Handle<ColorMaterial>>,
     pub car_scale: f32,
+    /// Size of the car texture before `car_scale`.
+    pub car_size: Vec2,
+    pub track: Handle<TrackDescription>,
+    pub road: Handle<ColorMaterial>,
+    pub input: Handle<InputConfig>,
 }
 
 impl FromWorld for GameResources {
@@ -18,10 +28,17 @@ impl FromWorld for GameResources {
         let asset_server = world
             .get_resource_mut::<AssetServer>()
             .expect("AssetServer not accessible");
-        let car = asset_server.load("green_car.png");
+        let cars = ["green_car.png", "blue_car.png", "red_car.png"]
+            .iter()
+            .map(|path| materials.add(asset_server.load(*path).into()))
+            .collect();
         GameResources {
-            car: materials.add(car.into()),
-            car_scale: 0.6
+            cars,
+            car_scale: 0.6,
+            car_size: vec2(60.0, 30.0),
+            track: asset_server.load("tracks/default.track.ron"),
+            road: materials.add(ColorMaterial::color(Color::rgb(0.3, 0.3, 0.5))),
+            input: asset_server.load("input/default.input.ron"),
         }
      }
 }
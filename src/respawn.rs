@@ -0,0 +1,129 @@
+use bevy::{
+    app::{AppBuilder, Plugin},
+    ecs::{
+        entity::Entity,
+        query::{With, Without},
+        schedule::SystemLabel,
+        system::{Commands, IntoSystem, Query, Res},
+    },
+    math::{Quat, Vec3},
+    prelude::ParallelSystemDescriptorCoercion,
+    transform::components::Transform,
+};
+use serde::{Deserialize, Serialize};
+use crate::race::{self, Checkpoint, RaceProgress};
+use crate::road::RoadMesh;
+use crate::simulation::{Simulated, SimulationStage, SimulationSystem, SimulationTime};
+use crate::track::Centerline;
+use crate::vehicle::VehicleState;
+
+/// Distance between places across the road, a car and some room.
+const SPACING: f32 = 30f32;
+/// Distance between rows of places along the road, they go back from where the car is put.
+const ROW_LENGTH: f32 = 50f32;
+/// Places closer than that to a car are taken, it is less than the spacing of places across the road.
+const CLEARANCE: f32 = 28f32;
+/// Places tried before a car is put on the first one anyway.
+const PLACES: usize = 12;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+pub enum RespawnPluginSystem {
+    Respawn,
+}
+
+pub struct RespawnPlugin;
+
+impl Plugin for RespawnPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<RespawnPolicy>()
+            .add_system_to_stage(
+                SimulationStage,
+                respawn.system()
+                    .label(RespawnPluginSystem::Respawn)
+                    .after(SimulationSystem::Collision)
+                    .before(SimulationSystem::Race));
+    }
+}
+
+/// Where respawned cars are put.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum RespawnPolicy {
+    /// On the last passed checkpoint, or on the start line before the first one.
+    #[default]
+    LastCheckpoint,
+    /// On the closest point of the centerline, facing along the road.
+    NearestRoadPoint,
+}
+
+/// Marks a car to be respawned on the next tick.
+pub struct Respawn;
+
+fn respawn(
+    mut commands: Commands,
+    time: Res<SimulationTime>,
+    policy: Res<RespawnPolicy>,
+    checkpoints: Query<&Checkpoint>,
+    roads: Query<(&RoadMesh, &Centerline, &Transform)>,
+    mut cars: Query<(Entity, &mut VehicleState, &mut Simulated, Option<&mut RaceProgress>), With<Respawn>>,
+    others: Query<&Simulated, (With<VehicleState>, Without<Respawn>)>,
+) {
+    // cars respawned on the same tick take places one after the other.
+    let mut taken: Vec<Vec3> = others.iter().map(|body| body.current.translation).collect();
+    let road = roads.single().ok();
+    for (car, mut state, mut body, progress) in cars.iter_mut() {
+        commands.entity(car).remove::<Respawn>();
+        let (road, centerline, road_transform) = match road {
+            Some(road) => road,
+            None => continue,
+        };
+        // the centerline starts on the start line.
+        let (distance, at_start) = match *policy {
+            RespawnPolicy::LastCheckpoint => {
+                let last_checkpoint = progress.as_ref().and_then(|progress| progress.last_checkpoint());
+                match last_checkpoint.and_then(|index| checkpoints.iter().find(|checkpoint| checkpoint.index == index)) {
+                    Some(checkpoint) => {
+                        let gate = checkpoint.gate;
+                        (centerline.distance_along((gate.left + gate.right) / 2f32), false)
+                    },
+                    None => (Some(0f32), true),
+                }
+            },
+            RespawnPolicy::NearestRoadPoint => (centerline.distance_along(body.current.translation), false),
+        };
+        let distance = match distance {
+            Some(distance) => distance,
+            None => continue,
+        };
+        let places: Vec<(Vec3, Vec3)> =
+            race::road_grid(road, road_transform, centerline, distance, 0f32, SPACING, ROW_LENGTH)
+                .take(PLACES)
+                .collect();
+        let free = |(place, _direction): &&(Vec3, Vec3)| {
+            taken.iter().all(|car| car.truncate().distance(place.truncate()) >= CLEARANCE)
+        };
+        let place = places.iter()
+            .find(free)
+            .or_else(|| places.first())
+            .copied()
+            .or_else(|| centerline.at(distance).map(|(position, direction, _width)| (position, direction)));
+        let (position, direction) = match place {
+            Some(place) => place,
+            None => continue,
+        };
+        taken.push(position);
+        let transform = Transform {
+            translation: position.truncate().extend(body.current.translation.z),
+            rotation: Quat::from_rotation_z(direction.y.atan2(direction.x)),
+            scale: body.current.scale,
+        };
+        body.teleport(transform);
+        *state = VehicleState::default();
+        if let Some(mut progress) = progress {
+            progress.teleported();
+            if at_start && !centerline.closed {
+                progress.standing_start(time.seconds());
+            }
+        }
+    }
+}
@@ -1,93 +1,186 @@
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+use std::fmt;
 use bevy::render::mesh::{Indices, Mesh};
 use bevy::math::{Vec3, vec3};
 use bevy::render::pipeline::PrimitiveTopology;
+use serde::{Deserialize, Serialize};
+use crate::spatial_hash::{Aabb, SpatialHash};
 
-pub type Shift = (f32, Vec3);
+/// Vertices closer than that are merged into one.
+pub const WELD_TOLERANCE: f32 = 0.01;
+/// Size of the cells indexing triangles.
+const CELL_SIZE: f32 = 64f32;
+
+pub type Shift = (Width, Vec3);
 pub type SectionDescription = Vec<Shift>;
 
+#[derive(Default)]
 pub struct Trajectory {
     positions: Vec<[f32; 3]>,
     normals: Vec<[f32; 3]>,
     uvs: Vec<[f32; 2]>,
 }
 
+/// Distances from the centerline to the road borders.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Width {
+    pub left: f32,
+    pub right: f32,
+}
+
+impl Width {
+    pub fn symmetric(width: f32) -> Self {
+        Width { left: width, right: width }
+    }
+
+    pub fn lerp(self, other: Width, ratio: f32) -> Self {
+        Width {
+            left: self.left + (other.left - self.left) * ratio,
+            right: self.right + (other.right - self.right) * ratio,
+        }
+    }
+
+    /// Width on the side of `normal` when `side` is positive, on the opposite otherwise.
+    fn on_side(self, side: f32) -> f32 {
+        if side > 0f32 { self.right } else { self.left }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Pivot {
     position: Vec3,
     direction: Vec3,
-    width: f32
-}
-
-
-pub fn generate_sections() -> Vec<SectionDescription> {
-    let x = 50f32;
-    let y = 50f32;
-    let width = 30f32;
-    vec![
-        vec![
-             (width, vec3(x, 0.0, 0.0))
-        ],
-        vec![
-             (width, vec3(x, 0.0, 0.0)),
-        ],
-        vec![
-             (width, vec3(x, y, 0.0)),
-             (width, vec3(x, 0.0, 0.0)),
-             (width, vec3(x, -y, 0.0)),
-             (width, vec3(000.0, -y, 0.0)),
-             (width, vec3(000.0, -y, 0.0)),
-        ],
-        vec![
-             (width, vec3(-x, -y, 0.0)),
-             (width, vec3(-x, -y, 0.0)),
-             (width, vec3(-x, -y, 0.0)),
-             (width, vec3(-0.0, -y, 0.0)),
-             (width, vec3(x, -0.0, 0.0)),
-             (width, vec3(x, -0.0, 0.0)),
-             (width, vec3(x, y, 0.0)),
-             (width, vec3(x, y, 0.0)),
-             (width, vec3(x, y, 0.0)),
-             (width, vec3(x, y, 0.0)),
-             (width, vec3(0.0, y, 0.0)),
-             (width, vec3(0.0, y, 0.0)),
-             (width, vec3(0.0, y, 0.0)),
-             (width, vec3(0.0, y, 0.0)),
-             (width, vec3(0.0, y, 0.0)),
-             (width, vec3(-x, 0.0, 0.0)),
-             (width, vec3(-x, 0.0, 0.0)),
-             (width, vec3(-x, y, 0.0)),
-             (width, vec3(-x, 0.0, 0.0)),
-             (width, vec3(-x, 0.0, 0.0)),
-             (width, vec3(-x, 0.0, 0.0)),
-             (width, vec3(-x, 0.0, 0.0)),
-             (width, vec3(-x, 0.0, 0.0)),
-             (width, vec3(-x, 0.0, 0.0)),
-             (width, vec3(-x, -y, 0.0)),
-             (width, vec3(0.0, -y, 0.0)),
-             (width, vec3(0.0, -y, 0.0)),
-             (width, vec3(x, -y, 0.0)),
-             (width, vec3(x, 0.0, 0.0)),
-        ],
-    ]
-}
-
-pub fn generate_road(sections: &[SectionDescription]) -> Mesh {
+    width: Width,
+    /// Length of the centerline before the pivot.
+    distance: f32,
+}
+
+impl Pivot {
+    pub fn new(position: Vec3, direction: Vec3, width: Width) -> Self {
+        Pivot { position, direction, width, distance: 0f32 }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        self.position
+    }
+
+    pub fn direction(&self) -> Vec3 {
+        self.direction
+    }
+
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    /// Pivot at the origin, continuing the first shift of the road.
+    pub fn start_of(sections: &[SectionDescription]) -> Self {
+        let (width, direction) = sections.iter()
+            .flatten()
+            .next()
+            .copied()
+            .unwrap_or((Width::symmetric(30f32), Vec3::X));
+        Pivot::new(Vec3::ZERO, direction, width)
+    }
+}
+
+/// Position with `[u, v]`, U goes across the road from left to right,
+/// V is the distance along the centerline.
+type Vertex = (Vec3, [f32; 2]);
+
+/// How the outer side of a turn is closed between two segments.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JoinStyle {
+    /// Sharp corner, replaced by a bevel when the corner is further
+    /// than `limit` widths from the centerline.
+    Miter { limit: f32 },
+    Bevel,
+    /// Arc built from `segments` triangles per half turn.
+    Round { segments: u32 },
+}
+
+impl Default for JoinStyle {
+    fn default() -> Self {
+        JoinStyle::Miter { limit: 4f32 }
+    }
+}
+
+/// How a segment reaches its width, starting from the width of the previous one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Taper {
+    /// Width switches at the start of the segment.
+    Step,
+    #[default]
+    Linear,
+    /// Smoothstep built from `slices` pieces.
+    Ease { slices: u32 },
+}
+
+impl Taper {
+    fn start_width(self, previous: Width, width: Width) -> Width {
+        match self {
+            Taper::Step => width,
+            Taper::Linear | Taper::Ease { .. } => previous,
+        }
+    }
+}
+
+/// A `closed` road has to end at its start, see `close_loop`. The start pivot
+/// continues its last segment then, so the seam gets the same joint as any turn.
+/// Returns the pivot at the end of every section as well.
+pub fn generate_road(
+    start: Pivot,
+    sections: &[SectionDescription],
+    join: JoinStyle,
+    taper: Taper,
+    closed: bool,
+) -> (RoadMesh, Vec<Pivot>) {
     let mut trajectory = Vec::new();
-    let mut current_pivot = Pivot {
-        position: Vec3::ZERO,
-        direction: Vec3::X,
-        width: 30f32
-    };
-    for section in sections.iter() {
-        let (new_pivot, section_trajectory) = build_section(current_pivot, section);
+    let mut pivots = Vec::with_capacity(sections.len());
+    let mut current_pivot = start;
+    let first = sections.iter().flatten().next();
+    let last = sections.iter().flatten().last();
+    if let (true, Some((width, direction))) = (closed, last) {
+        current_pivot.width = *width;
+        current_pivot.direction = *direction;
+    }
+    for (index, section) in sections.iter().enumerate() {
+        let next = match sections.get(index + 1) {
+            Some(section) => section.first(),
+            None if closed => first,
+            None => None,
+        };
+        let (new_pivot, section_trajectory) = build_section(current_pivot, section, next, join, taper);
         trajectory.push(section_trajectory);
+        pivots.push(new_pivot);
         current_pivot = new_pivot;
     }
-    trajectory_to_mesh(trajectory)
+    (RoadMesh::weld(trajectory, WELD_TOLERANCE), pivots)
+}
+
+/// Adds a shift from the end of the road back to its start, unless it is already there.
+pub fn close_loop(start: Vec3, sections: &mut Vec<SectionDescription>) {
+    let end = sections.iter()
+        .flatten()
+        .fold(start, |position, (_width, shift)| position + *shift);
+    let gap = start - end;
+    if gap.length() <= WELD_TOLERANCE {
+        return;
+    }
+    let width = sections.iter()
+        .flatten()
+        .last()
+        .map(|(width, _shift)| *width)
+        .unwrap_or(Width::symmetric(30f32));
+    match sections.last_mut() {
+        Some(section) => section.push((width, gap)),
+        None => sections.push(vec![(width, gap)]),
+    }
 }
 
-pub fn sections_into_line(sections: &[SectionDescription]) -> Vec<Vec3> {
+pub fn sections_into_line(start: Vec3, sections: &[SectionDescription]) -> Vec<Vec3> {
     let mut result = Vec::with_capacity(100);
-    let mut position = Vec3::ZERO;
+    let mut position = start;
     result.push(position);
     for section in sections.iter() {
         for (_width, shift) in section.iter() {
@@ -98,89 +191,668 @@ pub fn sections_into_line(sections: &[SectionDescription]) -> Vec<Vec3> {
     result
 }
 
-pub fn build_section(pivot: Pivot, description: &SectionDescription) -> (Pivot, Trajectory) {
-    let mut positions = Vec::new();
-    let mut section_width = Vec::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SegmentId {
+    pub section: usize,
+    pub segment: usize,
+}
+
+/// Two segments of the road closer to each other than their widths.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Overlap {
+    pub first: SegmentId,
+    pub second: SegmentId,
+    /// Distance between the centerlines of the segments.
+    pub distance: f32,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "section {} segment {} and section {} segment {} are {} apart",
+            self.first.section,
+            self.first.segment,
+            self.second.section,
+            self.second.segment,
+            self.distance)
+    }
+}
+
+/// Reports every pair of segments whose road surfaces overlap. Segments
+/// closer along the centerline than their widths are not compared, they
+/// overlap at joints anyway. A road ending at its start is treated as a loop.
+pub fn find_overlaps(start: Vec3, sections: &[SectionDescription]) -> Vec<Overlap> {
+    struct Segment {
+        id: SegmentId,
+        from: Vec3,
+        to: Vec3,
+        width: f32,
+        distance: f32,
+    }
+    let mut segments = Vec::new();
+    let mut position = start;
+    let mut distance = 0f32;
+    let mut previous_width = sections.iter()
+        .flatten()
+        .next()
+        .map(|(width, _shift)| *width);
+    for (section_index, section) in sections.iter().enumerate() {
+        for (segment_index, (width, shift)) in section.iter().enumerate() {
+            let widest = |width: Width| width.left.max(width.right);
+            let segment_width = previous_width.map(widest).unwrap_or(0f32).max(widest(*width));
+            segments.push(Segment {
+                id: SegmentId { section: section_index, segment: segment_index },
+                from: position,
+                to: position + *shift,
+                width: segment_width,
+                distance,
+            });
+            position += *shift;
+            distance += shift.length();
+            previous_width = Some(*width);
+        }
+    }
+    let closed = position.distance(start) <= WELD_TOLERANCE;
+    let mut overlaps = Vec::new();
+    for (index, first) in segments.iter().enumerate() {
+        let first_end = first.distance + first.from.distance(first.to);
+        for second in segments[index + 1..].iter() {
+            let second_end = second.distance + second.from.distance(second.to);
+            let mut gap = second.distance - first_end;
+            if closed {
+                gap = gap.min(distance - second_end + first.distance);
+            }
+            let reach = first.width + second.width;
+            if gap < reach {
+                continue;
+            }
+            let centerlines = segments_distance((first.from, first.to), (second.from, second.to));
+            if centerlines < reach {
+                overlaps.push(Overlap { first: first.id, second: second.id, distance: centerlines });
+            }
+        }
+    }
+    overlaps
+}
+
+/// Shortest distance between two segments on the XY plane.
+pub fn segments_distance((a, b): (Vec3, Vec3), (c, d): (Vec3, Vec3)) -> f32 {
+    let side = |from: Vec3, to: Vec3, point: Vec3| (to - from).cross(point - from).z;
+    let crossing = side(a, b, c) * side(a, b, d) < 0f32 && side(c, d, a) * side(c, d, b) < 0f32;
+    if crossing {
+        return 0f32;
+    }
+    point_to_segment(a, (c, d))
+        .min(point_to_segment(b, (c, d)))
+        .min(point_to_segment(c, (a, b)))
+        .min(point_to_segment(d, (a, b)))
+}
+
+pub fn point_to_segment(point: Vec3, (from, to): (Vec3, Vec3)) -> f32 {
+    let segment = to - from;
+    let length = segment.length_squared();
+    if length <= f32::EPSILON {
+        return point.distance(from);
+    }
+    let t = ((point - from).dot(segment) / length).clamp(0f32, 1f32);
+    point.distance(from + segment * t)
+}
+
+/// `next` is the first shift of the following section. It is required to
+/// shape the end of the last segment, the joint itself is built by the next section.
+pub fn build_section(
+    pivot: Pivot,
+    description: &SectionDescription,
+    next: Option<&Shift>,
+    join: JoinStyle,
+    taper: Taper,
+) -> (Pivot, Trajectory) {
+    let mut vertices = Vec::new();
+    let first = match description.first() {
+        Some(first) => *first,
+        None => return (pivot, Trajectory::default()),
+    };
     let mut current_position = pivot.position;
-    positions.push(pivot.position);
-    section_width.push(pivot.width);
-    for (width, point) in description.iter() {
-        current_position += *point;
-        positions.push(current_position);
-        section_width.push(*width);
-    }
-    let mut current_normal = normal(pivot.direction);
-    let mut current_direction = pivot.direction;
-    let mut current_width = pivot.width;
-    let mut trajectory_positions = Vec::new();
-    for index in 0..(positions.len() - 1) {
-        let new_width = *section_width.get(index).unwrap();
-        let first = *positions.get(index).unwrap();
-        let next = *positions.get(index + 1).unwrap();
-        let new_direction = next - first;
-        let new_normal = normal(new_direction);
-        let first_middle = first + new_normal * new_width;
-        let first_right = next + new_normal * new_width;
-        let first_bottom = first - new_normal * new_width;
-        let next_middle = first_right;
-        let next_left = first_bottom;
-        let next_bottom = next - new_normal * new_width;
-        if new_normal != current_normal {
-            trajectory_positions.push(first_bottom);
-            trajectory_positions.push(first_middle);
-            let first_left = first + current_normal * current_width;
-            trajectory_positions.push(first_left);
-            // temporary solution. I don't know how to process this case in any other way.
-            trajectory_positions.push(first_bottom);
-            trajectory_positions.push(first_middle);
-            let first_left = first - current_normal * current_width;
-            trajectory_positions.push(first_left);
-        }
-        trajectory_positions.push(first_middle);
-        trajectory_positions.push(first_right);
-        trajectory_positions.push(first_bottom);
-        trajectory_positions.push(next_bottom);
-        trajectory_positions.push(next_left);
-        trajectory_positions.push(next_middle);
-        current_width = new_width;
-        current_normal = new_normal;
-        current_direction = new_direction
+    let mut distance = pivot.distance;
+    let mut previous_width = pivot.width;
+    let (first_width, first_direction) = first;
+    let mut joint = build_joint(
+        current_position,
+        (pivot.width, pivot.direction),
+        (taper.start_width(pivot.width, first_width), first_direction),
+        join);
+    for (index, (width, direction)) in description.iter().enumerate() {
+        for triangle in joint.fill.drain(..) {
+            let [a, b, c] = triangle.map(|(position, u)| (position, [u, distance]));
+            push_triangle(&mut vertices, a, b, c);
+        }
+        let start = joint.outgoing;
+        let next_position = current_position + *direction;
+        let next_distance = distance + direction.length();
+        let end = match description.get(index + 1).or(next) {
+            Some((following_width, following_direction)) => {
+                joint = build_joint(
+                    next_position,
+                    (*width, *direction),
+                    (taper.start_width(*width, *following_width), *following_direction),
+                    join);
+                joint.incoming
+            },
+            None => Edge::across(next_position, *direction, *width),
+        };
+        let start_width = taper.start_width(previous_width, *width);
+        let mut edges = vec![(start, distance)];
+        match taper {
+            Taper::Ease { slices } if start_width != *width => {
+                for slice in 1..slices {
+                    let ratio = slice as f32 / slices as f32;
+                    let eased = ratio * ratio * (3f32 - 2f32 * ratio);
+                    let slice_position = current_position + *direction * ratio;
+                    let slice_width = start_width.lerp(*width, eased);
+                    edges.push((
+                        Edge::across(slice_position, *direction, slice_width),
+                        distance + (next_distance - distance) * ratio,
+                    ));
+                }
+            },
+            _ => {},
+        }
+        edges.push((end, next_distance));
+        for pair in edges.windows(2) {
+            let (start, start_distance) = pair[0];
+            let (end, end_distance) = pair[1];
+            let start_left = (start.left, [0f32, start_distance]);
+            let start_right = (start.right, [1f32, start_distance]);
+            let end_left = (end.left, [0f32, end_distance]);
+            let end_right = (end.right, [1f32, end_distance]);
+            push_triangle(&mut vertices, start_left, start_right, end_right);
+            push_triangle(&mut vertices, start_left, end_right, end_left);
+        }
+        current_position = next_position;
+        distance = next_distance;
+        previous_width = *width;
     }
     let trajectory = Trajectory {
-        positions: trajectory_positions.iter().map(|position| [position.x, position.y, position.z]).collect(),
-        normals: (0..trajectory_positions.len()).map(|_| [1.0, 1.0, 1.0]).collect(),
-        uvs: (0..trajectory_positions.len()).map(|_| [0.0, 0.0]).collect()
+        positions: vertices.iter().map(|(position, _uv)| [position.x, position.y, position.z]).collect(),
+        normals: vertices.iter().map(|_| [0.0, 0.0, 1.0]).collect(),
+        uvs: vertices.iter().map(|(_position, uv)| *uv).collect()
     };
-    let current_position = *positions.last().unwrap();
+    let (width, direction) = *description.last().unwrap();
     (
         Pivot {
             position: current_position,
-            direction: current_direction,
-            width: current_width
+            direction,
+            width,
+            distance,
         },
         trajectory
     )
 }
 
-pub fn trajectory_to_mesh(descriptions: Vec<Trajectory>) -> Mesh {
-    let mut positions = Vec::new();
-    let mut normals = Vec::new();
-    let mut uvs = Vec::new();
-    for description in descriptions.into_iter() {
-        positions.extend(description.positions);
-        normals.extend(description.normals);
-        uvs.extend(description.uvs);
+/// Road cross-section at a point, `right` lies on the side of `normal`.
+#[derive(Debug, Clone, Copy)]
+pub struct Edge {
+    pub left: Vec3,
+    pub right: Vec3,
+}
+
+impl Edge {
+    pub fn across(position: Vec3, direction: Vec3, width: Width) -> Self {
+        let normal = normal(direction);
+        Edge {
+            left: position - normal * width.left,
+            right: position + normal * width.right,
+        }
+    }
+}
+
+pub struct Joint {
+    /// End of the segment coming into the joint.
+    pub incoming: Edge,
+    /// Start of the segment going out of the joint.
+    pub outgoing: Edge,
+    /// Triangles closing the outer side of the turn, with U of every corner.
+    pub fill: Vec<[(Vec3, f32); 3]>,
+}
+
+/// Inner corners of both segments meet at the intersection of the inner
+/// borders, the gap on the outer side is closed according to `join`.
+pub fn build_joint(position: Vec3, incoming: Shift, outgoing: Shift, join: JoinStyle) -> Joint {
+    let (in_width, in_direction) = incoming;
+    let (out_width, out_direction) = outgoing;
+    let turn = in_direction.cross(out_direction).z;
+    let straight = turn.abs() <= f32::EPSILON * in_direction.length() * out_direction.length();
+    if straight && in_direction.dot(out_direction) > 0f32 {
+        return Joint {
+            incoming: Edge::across(position, in_direction, in_width),
+            outgoing: Edge::across(position, out_direction, out_width),
+            fill: Vec::new(),
+        };
+    }
+    // normal points to the right, so the right side is the outer one on a left turn.
+    let side = if turn >= 0f32 { 1f32 } else { -1f32 };
+    let in_normal = normal(in_direction) * side;
+    let out_normal = normal(out_direction) * side;
+    let (in_outer_width, out_outer_width) = (in_width.on_side(side), out_width.on_side(side));
+    let in_offset = in_normal * in_outer_width;
+    let out_offset = out_normal * out_outer_width;
+    let outer_in = position + in_offset;
+    let outer_out = position + out_offset;
+    let inner_in = position - in_normal * in_width.on_side(-side);
+    let inner_out = position - out_normal * out_width.on_side(-side);
+    // the inner corner may take at most half of each segment, the rest belongs to the other joint.
+    // A sharper corner is pulled towards the centerline, both segments still end at it.
+    let (inner, scale) = match intersect(inner_in, in_direction, inner_out, out_direction) {
+        Some((t, u)) if t <= 0f32 && u >= 0f32 => {
+            let scale = if t < -0.5f32 { -0.5f32 / t } else { 1f32 };
+            let scale = if u > 0.5f32 { scale.min(0.5f32 / u) } else { scale };
+            (inner_in + in_direction * t, scale)
+        },
+        _ => (position, 0f32),
+    };
+    let outer_u = if side > 0f32 { 1f32 } else { 0f32 };
+    let position_u = in_width.left / (in_width.left + in_width.right).max(f32::EPSILON);
+    let center = position + (inner - position) * scale;
+    let center_u = position_u + (1f32 - outer_u - position_u) * scale;
+    let mut outline = vec![outer_in];
+    match join {
+        JoinStyle::Bevel => {},
+        JoinStyle::Miter { limit } => {
+            let miter = intersect(outer_in, in_direction, outer_out, out_direction)
+                .map(|(t, _u)| outer_in + in_direction * t)
+                .filter(|miter| {
+                    (*miter - position).length() <= limit * in_outer_width.max(out_outer_width)
+                });
+            outline.extend(miter);
+        },
+        JoinStyle::Round { segments } => {
+            let from = in_offset.y.atan2(in_offset.x);
+            let mut sweep = out_offset.y.atan2(out_offset.x) - from;
+            if sweep * side < 0f32 {
+                sweep += 2f32 * PI * side;
+            }
+            let steps = ((sweep.abs() / PI * segments as f32).ceil() as usize).max(1);
+            for step in 1..steps {
+                let ratio = step as f32 / steps as f32;
+                let angle = from + sweep * ratio;
+                let radius = in_outer_width + (out_outer_width - in_outer_width) * ratio;
+                outline.push(position + vec3(angle.cos(), angle.sin(), 0f32) * radius);
+            }
+        },
+    }
+    outline.push(outer_out);
+    let fill = outline.windows(2)
+        .map(|pair| [(center, center_u), (pair[0], outer_u), (pair[1], outer_u)])
+        .collect();
+    let edge = |outer: Vec3, inner: Vec3| if side > 0f32 {
+        Edge { left: inner, right: outer }
+    } else {
+        Edge { left: outer, right: inner }
+    };
+    Joint {
+        incoming: edge(outer_in, center),
+        outgoing: edge(outer_out, center),
+        fill,
+    }
+}
+
+/// Solves `a + t * a_direction == b + u * b_direction` on the XY plane.
+pub fn intersect(a: Vec3, a_direction: Vec3, b: Vec3, b_direction: Vec3) -> Option<(f32, f32)> {
+    let denominator = a_direction.x * b_direction.y - a_direction.y * b_direction.x;
+    if denominator.abs() <= f32::EPSILON {
+        return None;
+    }
+    let shift = b - a;
+    let t = (shift.x * b_direction.y - shift.y * b_direction.x) / denominator;
+    let u = (shift.x * a_direction.y - shift.y * a_direction.x) / denominator;
+    Some((t, u))
+}
+
+/// Pushes the triangle with counter-clockwise winding.
+fn push_triangle(vertices: &mut Vec<Vertex>, a: Vertex, b: Vertex, c: Vertex) {
+    let area = (b.0 - a.0).cross(c.0 - a.0).z;
+    vertices.push(a);
+    if area < 0f32 {
+        vertices.push(c);
+        vertices.push(b);
+    } else {
+        vertices.push(b);
+        vertices.push(c);
+    }
+}
+
+/// Triangle list of the road with shared vertices. Each triangle is
+/// three consecutive `indices`, `adjacency` keeps the triangle across
+/// each of its edges, edge `n` goes from corner `n` to corner `n + 1`.
+/// Vertices at the same place with different UVs stay apart, like the seam
+/// of a closed road, but triangles across them are still adjacent.
+pub struct RoadMesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+    adjacency: Vec<[Option<usize>; 3]>,
+    /// Triangles by their bounds on the XY plane.
+    index: SpatialHash<usize>,
+}
+
+impl RoadMesh {
+    /// Merges vertices closer than `tolerance` and drops triangles collapsed by it.
+    pub fn weld(descriptions: Vec<Trajectory>, tolerance: f32) -> Self {
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let mut indices = Vec::new();
+        // first vertex at the place of every vertex.
+        let mut places = Vec::new();
+        let mut grid: HashMap<(i32, i32), Vec<u32>> = HashMap::new();
+        let cell = |position: Vec3| (
+            (position.x / tolerance).floor() as i32,
+            (position.y / tolerance).floor() as i32,
+        );
+        for description in descriptions.into_iter() {
+            let vertices = description.positions.iter()
+                .zip(description.normals.iter())
+                .zip(description.uvs.iter());
+            let mut triangle = Vec::with_capacity(3);
+            for ((position, normal), uv) in vertices {
+                let position = Vec3::from(*position);
+                let (x, y) = cell(position);
+                let nearby: Vec<u32> = (x - 1..=x + 1)
+                    .flat_map(|x| (y - 1..=y + 1).map(move |y| (x, y)))
+                    .filter_map(|key| grid.get(&key))
+                    .flatten()
+                    .filter(|index| positions[**index as usize].distance(position) <= tolerance)
+                    .copied()
+                    .collect();
+                let same_uv = |index: &&u32| {
+                    let other: &[f32; 2] = &uvs[**index as usize];
+                    (other[0] - uv[0]).abs() <= tolerance && (other[1] - uv[1]).abs() <= tolerance
+                };
+                let index = match nearby.iter().find(same_uv) {
+                    Some(index) => *index,
+                    None => {
+                        let index = positions.len() as u32;
+                        places.push(nearby.first().map(|index| places[*index as usize]).unwrap_or(index));
+                        positions.push(position);
+                        normals.push(*normal);
+                        uvs.push(*uv);
+                        grid.entry((x, y)).or_default().push(index);
+                        index
+                    },
+                };
+                triangle.push(index);
+                if triangle.len() == 3 {
+                    let [a, b, c] = [0, 1, 2].map(|corner| places[triangle[corner] as usize]);
+                    if a != b && b != c && c != a {
+                        indices.extend(triangle.iter());
+                    }
+                    triangle.clear();
+                }
+            }
+        }
+        let adjacency = build_adjacency(&indices, &places);
+        let mut road = RoadMesh { positions, normals, uvs, indices, adjacency, index: SpatialHash::new(CELL_SIZE) };
+        let bounds: Vec<Aabb> = road.triangles()
+            .map(|corners| Aabb::from_points(&corners.map(|corner| corner.truncate())))
+            .collect();
+        for (triangle, aabb) in bounds.into_iter().enumerate() {
+            road.index.insert(triangle, aabb);
+        }
+        road
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    pub fn triangle(&self, index: usize) -> [Vec3; 3] {
+        let corners = &self.indices[index * 3..index * 3 + 3];
+        [
+            self.positions[corners[0] as usize],
+            self.positions[corners[1] as usize],
+            self.positions[corners[2] as usize],
+        ]
+    }
+
+    pub fn triangles(&self) -> impl Iterator<Item = [Vec3; 3]> + '_ {
+        (0..self.triangle_count()).map(move |index| self.triangle(index))
+    }
+
+    /// Edges used by a single triangle, that is the outline of the road.
+    pub fn boundary_edges(&self) -> Vec<[u32; 2]> {
+        self.adjacency.iter()
+            .enumerate()
+            .flat_map(|(triangle, neighbours)| {
+                let corners = &self.indices[triangle * 3..triangle * 3 + 3];
+                (0..3)
+                    .filter(move |edge| neighbours[*edge].is_none())
+                    .map(move |edge| [corners[edge], corners[(edge + 1) % 3]])
+            })
+            .collect()
+    }
+
+    /// Outline of the road chained into polylines, closed ones end at their first point.
+    /// Seams of a closed road split them, since vertices there are not shared.
+    pub fn boundary_polylines(&self) -> Vec<Vec<Vec3>> {
+        let edges = self.boundary_edges();
+        let mut outgoing: HashMap<u32, Vec<u32>> = HashMap::new();
+        for [from, to] in edges.iter() {
+            outgoing.entry(*from).or_default().push(*to);
+        }
+        let targets: HashSet<u32> = edges.iter().map(|[_from, to]| *to).collect();
+        // open chains go first, so they are not entered in the middle.
+        let starts = edges.iter()
+            .filter(|[from, _to]| !targets.contains(from))
+            .chain(edges.iter())
+            .map(|[from, _to]| *from);
+        let mut polylines = Vec::new();
+        for start in starts {
+            let mut current = start;
+            let mut polyline = vec![self.positions[start as usize]];
+            while let Some(next) = outgoing.get_mut(&current).and_then(|targets| targets.pop()) {
+                polyline.push(self.positions[next as usize]);
+                current = next;
+            }
+            if polyline.len() > 1 {
+                polylines.push(polyline);
+            }
+        }
+        polylines
+    }
+
+    /// Index of the triangle covering the point on the XY plane.
+    pub fn triangle_at(&self, point: Vec3) -> Option<usize> {
+        let position = point.truncate();
+        self.index.query(Aabb { min: position, max: position })
+            .into_iter()
+            .find(|triangle| {
+                let [a, b, c] = self.triangle(*triangle);
+                let side = |from: Vec3, to: Vec3| (to - from).cross(point - from).z;
+                let (ab, bc, ca) = (side(a, b), side(b, c), side(c, a));
+                (ab >= 0f32 && bc >= 0f32 && ca >= 0f32) || (ab <= 0f32 && bc <= 0f32 && ca <= 0f32)
+            })
+    }
+
+    pub fn to_mesh(&self) -> Mesh {
+        let positions: Vec<[f32; 3]> = self.positions.iter()
+            .map(|position| [position.x, position.y, position.z])
+            .collect();
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals.clone());
+        mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, self.uvs.clone());
+        mesh.set_indices(Some(Indices::U32(self.indices.clone())));
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh
+    }
+}
+
+fn build_adjacency(indices: &[u32], places: &[u32]) -> Vec<[Option<usize>; 3]> {
+    let mut edges: HashMap<(u32, u32), Vec<(usize, usize)>> = HashMap::new();
+    for (triangle, corners) in indices.chunks(3).enumerate() {
+        for edge in 0..3 {
+            let from = places[corners[edge] as usize];
+            let to = places[corners[(edge + 1) % 3] as usize];
+            edges.entry((from.min(to), from.max(to)))
+                .or_default()
+                .push((triangle, edge));
+        }
     }
-    let indices = (0..positions.len()).map(|value| value as u32).collect();
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-    mesh.set_indices(Some(Indices::U32(indices)));
-    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-    mesh
+    let mut adjacency = vec![[None; 3]; indices.len() / 3];
+    for shared in edges.values() {
+        // edges shared by more than two triangles are overlaps, they are not linked.
+        if let [(first, first_edge), (second, second_edge)] = shared.as_slice() {
+            adjacency[*first][*first_edge] = Some(*second);
+            adjacency[*second][*second_edge] = Some(*first);
+        }
+    }
+    adjacency
 }
 
 pub fn normal(vec: Vec3) -> Vec3 {
     // TODO: bug expected if direction is close to 0.
     vec.cross(Vec3::Z).normalize()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Square loop turning left, `sides` segments of 50 on every side and 30 to both borders.
+    fn square_loop(sides: usize) -> Vec<SectionDescription> {
+        let width = Width::symmetric(30f32);
+        let directions = [
+            vec3(50f32, 0f32, 0f32),
+            vec3(0f32, 50f32, 0f32),
+            vec3(-50f32, 0f32, 0f32),
+            vec3(0f32, -50f32, 0f32),
+        ];
+        vec![directions.iter().flat_map(|direction| vec![(width, *direction); sides]).collect()]
+    }
+
+    fn distance_to_line(point: Vec3, line: &[Vec3]) -> f32 {
+        line.windows(2)
+            .map(|pair| point_to_segment(point, (pair[0], pair[1])))
+            .fold(f32::MAX, f32::min)
+    }
+
+    #[test]
+    fn sharp_corners_are_watertight() {
+        let sections = square_loop(3);
+        let line = sections_into_line(Vec3::ZERO, &sections);
+        let joins = [JoinStyle::Bevel, JoinStyle::Miter { limit: 4f32 }, JoinStyle::Round { segments: 8 }];
+        for join in joins.iter() {
+            let start = Pivot::start_of(&sections);
+            let (road, _pivots) = generate_road(start, &sections, *join, Taper::Linear, true);
+            // inner corners are pulled in by at most 5, the outline never crosses the road.
+            for [from, to] in road.boundary_edges() {
+                let middle = (road.positions[from as usize] + road.positions[to as usize]) / 2f32;
+                assert!(distance_to_line(middle, &line) > 20f32, "{:?} has an edge inside at {}", join, middle);
+            }
+            assert_eq!(road.boundary_polylines().len(), 2, "{:?}", join);
+        }
+    }
+
+    fn segment(section: usize, segment: usize) -> SegmentId {
+        SegmentId { section, segment }
+    }
+
+    #[test]
+    fn figure_eight_overlaps_where_it_crosses() {
+        let width = Width::symmetric(10f32);
+        let sections = vec![vec![
+            (width, vec3(200f32, 200f32, 0f32)),
+            (width, vec3(0f32, -200f32, 0f32)),
+            (width, vec3(-200f32, 200f32, 0f32)),
+            (width, vec3(0f32, -200f32, 0f32)),
+        ]];
+        let overlaps = find_overlaps(Vec3::ZERO, &sections);
+        assert_eq!(overlaps, vec![Overlap { first: segment(0, 0), second: segment(0, 2), distance: 0f32 }]);
+    }
+
+    #[test]
+    fn clean_closed_loop_does_not_overlap() {
+        assert!(find_overlaps(Vec3::ZERO, &square_loop(3)).is_empty());
+    }
+
+    /// Goes along X and comes back 50 higher, the sides reach 48 and 52 of each other.
+    #[test]
+    fn near_misses_depend_on_the_width() {
+        let hairpin = |width: Width| {
+            let out = vec![(width, vec3(100f32, 0f32, 0f32)); 3];
+            let back = vec![(width, vec3(-100f32, 0f32, 0f32)); 3];
+            vec![out, vec![(width, vec3(0f32, 50f32, 0f32))], back]
+        };
+        assert!(find_overlaps(Vec3::ZERO, &hairpin(Width::symmetric(24f32))).is_empty());
+        // only the widest side counts.
+        let wide = find_overlaps(Vec3::ZERO, &hairpin(Width { left: 26f32, right: 10f32 }));
+        // the segments next to the turn are too close along the road to count.
+        let pairs: Vec<(usize, usize)> = wide.iter()
+            .inspect(|overlap| assert_eq!((overlap.first.section, overlap.second.section, overlap.distance), (0, 2, 50f32)))
+            .map(|overlap| (overlap.first.segment, overlap.second.segment))
+            .collect();
+        assert_eq!(pairs, vec![(0, 1), (0, 2), (1, 0), (1, 1), (1, 2), (2, 1)]);
+    }
+
+    /// Straight road 10 wide to both sides, widening to 30 along its second half.
+    fn widening_road(taper: Taper) -> RoadMesh {
+        let sections = vec![
+            vec![(Width::symmetric(10f32), vec3(100f32, 0f32, 0f32))],
+            vec![(Width::symmetric(30f32), vec3(100f32, 0f32, 0f32))],
+        ];
+        generate_road(Pivot::start_of(&sections), &sections, JoinStyle::default(), taper, false).0
+    }
+
+    /// Whether the road reaches `border` across at `x` and not much further.
+    fn border_at(road: &RoadMesh, x: f32, border: f32) -> bool {
+        let covered = |y: f32| [y, -y].iter().all(|y| road.triangle_at(vec3(x, *y, 0f32)).is_some());
+        covered(border - 0.5) && !covered(border + 0.5)
+    }
+
+    #[test]
+    fn step_taper_switches_width_at_the_segment_start() {
+        let road = widening_road(Taper::Step);
+        assert!(border_at(&road, 99f32, 10f32));
+        assert!(border_at(&road, 101f32, 30f32));
+        assert!(border_at(&road, 150f32, 30f32));
+    }
+
+    #[test]
+    fn linear_taper_widens_evenly() {
+        let road = widening_road(Taper::Linear);
+        assert!(border_at(&road, 101f32, 10.2));
+        assert!(border_at(&road, 125f32, 15f32));
+        assert!(border_at(&road, 150f32, 20f32));
+        assert!(border_at(&road, 199f32, 29.8));
+    }
+
+    /// Smoothstep of a quarter is 0.15625, of a half 0.5.
+    #[test]
+    fn eased_taper_widens_slowly_at_both_ends() {
+        let road = widening_road(Taper::Ease { slices: 4 });
+        assert!(border_at(&road, 125f32, 13.125));
+        assert!(border_at(&road, 150f32, 20f32));
+        assert!(border_at(&road, 175f32, 26.875));
+    }
+
+    #[test]
+    fn triangle_at_finds_the_covering_triangle() {
+        let sections = square_loop(3);
+        let (road, _pivots) = generate_road(
+            Pivot::start_of(&sections), &sections, JoinStyle::default(), Taper::Linear, true);
+        let covers = |triangle: usize, point: Vec3| {
+            let [a, b, c] = road.triangle(triangle);
+            let side = |from: Vec3, to: Vec3| (to - from).cross(point - from).z;
+            let (ab, bc, ca) = (side(a, b), side(b, c), side(c, a));
+            (ab >= 0f32 && bc >= 0f32 && ca >= 0f32) || (ab <= 0f32 && bc <= 0f32 && ca <= 0f32)
+        };
+        for x in -10..=40 {
+            for y in -10..=40 {
+                let point = vec3(x as f32 * 5f32, y as f32 * 5f32, 0f32);
+                let expected = (0..road.triangle_count()).find(|triangle| covers(*triangle, point));
+                assert_eq!(road.triangle_at(point), expected, "at {}", point);
+            }
+        }
+    }
+}
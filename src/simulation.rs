@@ -0,0 +1,107 @@
+use bevy::{
+    app::{AppBuilder, CoreStage, Plugin},
+    core::{FixedTimestep, FixedTimesteps},
+    ecs::{
+        schedule::{StageLabel, SystemLabel, SystemStage},
+        system::{IntoSystem, Query, Res, ResMut},
+    },
+    prelude::ParallelSystemDescriptorCoercion,
+    transform::{components::Transform, TransformSystem},
+};
+
+/// Simulation runs with the same step whatever the frame rate is.
+pub const TIMESTEP: f64 = 1f64 / 60f64;
+const TIMESTEP_LABEL: &str = "simulation";
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, StageLabel)]
+pub struct SimulationStage;
+
+/// Systems of the simulation stage run in this order.
+/// The stage only reads inputs written before it, so the same
+/// inputs on every tick give the same results.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+pub enum SimulationSystem {
+    Begin,
+    Movement,
+    Collision,
+    Race,
+    /// Runs after the stage, once per frame in `CoreStage::PostUpdate`.
+    Interpolate,
+}
+
+pub struct SimulationPlugin;
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<SimulationTime>()
+            .add_stage_after(
+                CoreStage::Update,
+                SimulationStage,
+                SystemStage::parallel()
+                    .with_run_criteria(FixedTimestep::step(TIMESTEP).with_label(TIMESTEP_LABEL)))
+            .add_system_to_stage(SimulationStage, begin_tick.system().label(SimulationSystem::Begin))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                interpolate.system()
+                    .label(SimulationSystem::Interpolate)
+                    .before(TransformSystem::TransformPropagate));
+    }
+}
+
+/// Ticks of the simulation, it replaces `Time` for everything simulated.
+#[derive(Debug, Default)]
+pub struct SimulationTime {
+    pub tick: u64,
+}
+
+impl SimulationTime {
+    pub fn seconds(&self) -> f64 {
+        self.tick as f64 * TIMESTEP
+    }
+}
+
+/// Transform owned by the simulation, `Transform` is only rendered
+/// between the last two ticks of it.
+#[derive(Debug, Clone)]
+pub struct Simulated {
+    pub previous: Transform,
+    pub current: Transform,
+}
+
+impl Simulated {
+    pub fn new(transform: Transform) -> Self {
+        Simulated { previous: transform, current: transform }
+    }
+
+    /// Moves without interpolation from the previous place.
+    pub fn teleport(&mut self, transform: Transform) {
+        self.previous = transform;
+        self.current = transform;
+    }
+
+    pub fn interpolate(&self, ratio: f32) -> Transform {
+        Transform {
+            translation: self.previous.translation.lerp(self.current.translation, ratio),
+            rotation: self.previous.rotation.slerp(self.current.rotation, ratio),
+            scale: self.current.scale,
+        }
+    }
+}
+
+fn begin_tick(mut time: ResMut<SimulationTime>, mut bodies: Query<&mut Simulated>) {
+    time.tick += 1;
+    for mut body in bodies.iter_mut() {
+        body.previous = body.current;
+    }
+}
+
+fn interpolate(timesteps: Res<FixedTimesteps>, mut bodies: Query<(&Simulated, &mut Transform)>) {
+    let ratio = timesteps.get(TIMESTEP_LABEL)
+        .map(|state| state.overstep_percentage() as f32)
+        .unwrap_or(1f32)
+        .clamp(0f32, 1f32);
+    for (body, mut transform) in bodies.iter_mut() {
+        *transform = body.interpolate(ratio);
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use bevy::math::Vec2;
+
+/// Cells an item may cover before it is kept aside and returned by every query.
+const MAX_CELLS: i64 = 64;
+
+/// Axis aligned box on the XY plane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl Aabb {
+    pub fn from_points(points: &[Vec2]) -> Self {
+        let first = points.first().copied().unwrap_or(Vec2::ZERO);
+        points.iter().fold(Aabb { min: first, max: first }, |aabb, point| Aabb {
+            min: aabb.min.min(*point),
+            max: aabb.max.max(*point),
+        })
+    }
+
+    pub fn grow(self, margin: f32) -> Self {
+        Aabb { min: self.min - Vec2::splat(margin), max: self.max + Vec2::splat(margin) }
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x && other.min.x <= self.max.x
+            && self.min.y <= other.max.y && other.min.y <= self.max.y
+    }
+}
+
+/// Uniform grid of items by their bounds. Queries return candidates only,
+/// items near the query box may come back even if they do not touch it.
+pub struct SpatialHash<T> {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<T>>,
+    /// Items too large for the grid.
+    large: Vec<T>,
+}
+
+impl<T: Copy + Ord> SpatialHash<T> {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialHash { cell_size, cells: HashMap::new(), large: Vec::new() }
+    }
+
+    pub fn clear(&mut self) {
+        // cells are kept, so their memory is reused by the next frame.
+        for items in self.cells.values_mut() {
+            items.clear();
+        }
+        self.large.clear();
+    }
+
+    pub fn insert(&mut self, item: T, aabb: Aabb) {
+        let ((min_x, min_y), (max_x, max_y)) = (self.cell(aabb.min), self.cell(aabb.max));
+        let count = (max_x as i64 - min_x as i64 + 1) * (max_y as i64 - min_y as i64 + 1);
+        if count > MAX_CELLS {
+            self.large.push(item);
+            return;
+        }
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                self.cells.entry((x, y)).or_default().push(item);
+            }
+        }
+    }
+
+    /// Items of the cells covered by `aabb` in ascending order, each one once.
+    pub fn query(&self, aabb: Aabb) -> Vec<T> {
+        let ((min_x, min_y), (max_x, max_y)) = (self.cell(aabb.min), self.cell(aabb.max));
+        let mut found = self.large.clone();
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                if let Some(items) = self.cells.get(&(x, y)) {
+                    found.extend(items.iter());
+                }
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+    fn cell(&self, point: Vec2) -> (i32, i32) {
+        ((point.x / self.cell_size).floor() as i32, (point.y / self.cell_size).floor() as i32)
+    }
+}
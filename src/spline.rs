@@ -0,0 +1,103 @@
+use bevy::math::Vec3;
+
+/// Deepest subdivision of a single curve, 2^10 pieces at most.
+const MAX_DEPTH: u32 = 10;
+
+/// Cubic curve in Bézier form.
+#[derive(Debug, Clone, Copy)]
+pub struct Cubic {
+    pub start: Vec3,
+    pub control_a: Vec3,
+    pub control_b: Vec3,
+    pub end: Vec3,
+}
+
+impl Cubic {
+    pub fn bezier(start: Vec3, control_a: Vec3, control_b: Vec3, end: Vec3) -> Self {
+        Cubic { start, control_a, control_b, end }
+    }
+
+    /// Uniform Catmull-Rom piece between `start` and `end`.
+    pub fn catmull_rom(before: Vec3, start: Vec3, end: Vec3, after: Vec3) -> Self {
+        Cubic {
+            start,
+            control_a: start + (end - before) / 6f32,
+            control_b: end - (after - start) / 6f32,
+            end,
+        }
+    }
+
+    pub fn point(&self, t: f32) -> Vec3 {
+        let rest = 1f32 - t;
+        self.start * (rest * rest * rest)
+            + self.control_a * (3f32 * rest * rest * t)
+            + self.control_b * (3f32 * rest * t * t)
+            + self.end * (t * t * t)
+    }
+}
+
+/// Builds Catmull-Rom pieces passing through every point.
+/// Missing neighbours of the end points are mirrored,
+/// unless `incoming` direction is given for the first one.
+pub fn catmull_rom_chain(points: &[Vec3], incoming: Option<Vec3>) -> Vec<Cubic> {
+    if points.len() < 2 {
+        return Vec::new();
+    }
+    let first_length = (points[1] - points[0]).length();
+    let before = match incoming {
+        Some(direction) if direction.length() > f32::EPSILON =>
+            points[0] - direction.normalize() * first_length,
+        _ => points[0] * 2f32 - points[1],
+    };
+    let last = points.len() - 1;
+    let after = points[last] * 2f32 - points[last - 1];
+    (0..last)
+        .map(|index| {
+            let previous = if index == 0 { before } else { points[index - 1] };
+            let next = points.get(index + 2).copied().unwrap_or(after);
+            Cubic::catmull_rom(previous, points[index], points[index + 1], next)
+        })
+        .collect()
+}
+
+/// Samples curves into a polyline. Curves are split until the polyline
+/// deviates from them less than `tolerance`, so tight turns get more points
+/// than gentle ones. The start of the first curve is not included.
+pub fn sample(curves: &[Cubic], tolerance: f32) -> Vec<Vec3> {
+    let mut points = Vec::new();
+    for curve in curves.iter() {
+        subdivide(curve, (0f32, curve.start), (1f32, curve.end), tolerance, 0, &mut points);
+    }
+    points
+}
+
+fn subdivide(
+    curve: &Cubic,
+    (from, from_point): (f32, Vec3),
+    (to, to_point): (f32, Vec3),
+    tolerance: f32,
+    depth: u32,
+    points: &mut Vec<Vec3>,
+) {
+    let middle = (from + to) / 2f32;
+    let middle_point = curve.point(middle);
+    let flat = [0.25f32, 0.5, 0.75].iter()
+        .map(|ratio| curve.point(from + (to - from) * ratio))
+        .all(|point| distance_to_chord(point, from_point, to_point) <= tolerance);
+    if flat || depth >= MAX_DEPTH {
+        points.push(to_point);
+        return;
+    }
+    subdivide(curve, (from, from_point), (middle, middle_point), tolerance, depth + 1, points);
+    subdivide(curve, (middle, middle_point), (to, to_point), tolerance, depth + 1, points);
+}
+
+fn distance_to_chord(point: Vec3, start: Vec3, end: Vec3) -> f32 {
+    let chord = end - start;
+    let length = chord.length_squared();
+    if length <= f32::EPSILON {
+        return (point - start).length();
+    }
+    let t = ((point - start).dot(chord) / length).clamp(0f32, 1f32);
+    (point - (start + chord * t)).length()
+}
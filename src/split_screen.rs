@@ -0,0 +1,304 @@
+use bevy::{
+    app::{AppBuilder, CoreStage, Plugin},
+    ecs::{
+        entity::Entity,
+        query::Without,
+        schedule::SystemLabel,
+        system::{Commands, IntoSystem, Query, Res, ResMut},
+        world::World,
+    },
+    math::{vec2, Vec2},
+    prelude::ParallelSystemDescriptorCoercion,
+    render::{
+        camera::{ActiveCameras, Camera, CameraProjection, OrthographicProjection, ScalingMode},
+        entity::OrthographicCameraBundle,
+        pass::{
+            LoadOp, Operations, PassDescriptor, RenderPass, RenderPassDepthStencilAttachmentDescriptor,
+            TextureAttachment,
+        },
+        render_graph::{
+            base::{node, MainPass, Msaa},
+            CameraNode, Node, PassNode, RenderGraph, ResourceSlotInfo, ResourceSlots, WindowSwapChainNode,
+            WindowTextureNode,
+        },
+        renderer::{BufferId, RenderContext, RenderResourceBindings, RenderResourceContext, TextureId},
+        texture::Extent3d,
+    },
+    transform::{components::Transform, TransformSystem},
+    window::Windows,
+};
+use crate::actions::{Player, MAX_PLAYERS};
+use crate::simulation::SimulationSystem;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+pub enum SplitScreenPluginSystem {
+    Cameras,
+    Follow,
+}
+
+/// Every player gets a camera and a part of the window.
+pub struct SplitScreenPlugin;
+
+impl Plugin for SplitScreenPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .init_resource::<SplitScreen>()
+            .add_startup_system(setup.system())
+            .add_system(player_cameras.system().label(SplitScreenPluginSystem::Cameras))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                follow_players.system()
+                    .label(SplitScreenPluginSystem::Follow)
+                    .after(SimulationSystem::Interpolate)
+                    .before(TransformSystem::TransformPropagate));
+    }
+}
+
+/// Number of players sharing the window.
+#[derive(Debug, Default)]
+pub struct SplitScreen {
+    pub players: usize,
+}
+
+/// Camera of the player with the same id.
+pub struct PlayerCamera(pub usize);
+
+fn camera_name(player: usize) -> String {
+    format!("player_{}_camera", player)
+}
+
+/// Part of the window given to `player`, as the offset and the size
+/// in fractions of the window from its top left corner.
+/// Two players split it into the top and the bottom halves, more of them into quarters.
+pub fn viewport(player: usize, players: usize) -> (Vec2, Vec2) {
+    match players {
+        0 | 1 => (Vec2::ZERO, Vec2::ONE),
+        2 => (vec2(0f32, player as f32 / 2f32), vec2(1f32, 0.5f32)),
+        _ => (vec2((player % 2) as f32 / 2f32, (player / 2) as f32 / 2f32), Vec2::splat(0.5f32)),
+    }
+}
+
+/// A pass for every player drawing over the main one, which only clears the window then.
+fn setup(
+    mut graph: ResMut<RenderGraph>,
+    mut active_cameras: ResMut<ActiveCameras>,
+    msaa: Res<Msaa>,
+) {
+    for player in 0..MAX_PLAYERS {
+        let camera = camera_name(player);
+        active_cameras.add(&camera);
+        let camera_node = graph.add_system_node(
+            format!("player_{}_camera", player), CameraNode::new(camera.clone()));
+
+        let mut pass = PassNode::<&MainPass>::new(PassDescriptor {
+            color_attachments: vec![msaa.color_attachment_descriptor(
+                TextureAttachment::Input("color_attachment".to_string()),
+                TextureAttachment::Input("color_resolve_target".to_string()),
+                Operations { load: LoadOp::Load, store: true },
+            )],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachmentDescriptor {
+                attachment: TextureAttachment::Input("depth".to_string()),
+                depth_ops: Some(Operations { load: LoadOp::Clear(1f32), store: true }),
+                stencil_ops: None,
+            }),
+            sample_count: msaa.samples,
+        });
+        pass.add_camera(&camera);
+        let pass_node = graph.add_node(format!("player_{}_pass", player), ViewportNode { pass, player });
+
+        graph.add_node_edge(node::MAIN_PASS, pass_node).unwrap();
+        graph.add_node_edge(node::SHARED_BUFFERS, pass_node).unwrap();
+        graph.add_node_edge(camera_node, pass_node).unwrap();
+        let color = if msaa.samples > 1 { "color_resolve_target" } else { "color_attachment" };
+        graph.add_slot_edge(node::PRIMARY_SWAP_CHAIN, WindowSwapChainNode::OUT_TEXTURE, pass_node, color)
+            .unwrap();
+        if msaa.samples > 1 {
+            graph.add_slot_edge(
+                node::MAIN_SAMPLED_COLOR_ATTACHMENT, WindowTextureNode::OUT_TEXTURE, pass_node, "color_attachment")
+                .unwrap();
+        }
+        graph.add_slot_edge(node::MAIN_DEPTH_TEXTURE, WindowTextureNode::OUT_TEXTURE, pass_node, "depth")
+            .unwrap();
+    }
+}
+
+fn player_cameras(
+    mut commands: Commands,
+    mut split_screen: ResMut<SplitScreen>,
+    players: Query<&Player>,
+    cameras: Query<(Entity, &PlayerCamera)>,
+) {
+    let count = players.iter().count();
+    if split_screen.players != count {
+        split_screen.players = count;
+    }
+    for (camera, player_camera) in cameras.iter() {
+        if !players.iter().any(|player| player.0 == player_camera.0) {
+            commands.entity(camera).despawn();
+        }
+    }
+    for player in players.iter() {
+        if cameras.iter().any(|(_camera, player_camera)| player_camera.0 == player.0) {
+            continue;
+        }
+        let mut camera = OrthographicCameraBundle::new_2d();
+        camera.camera.name = Some(camera_name(player.0));
+        camera.orthographic_projection.scaling_mode = ScalingMode::None;
+        commands.spawn_bundle(camera).insert(PlayerCamera(player.0));
+    }
+}
+
+/// Centers cameras on their cars and fits them to their part of the window.
+fn follow_players(
+    windows: Res<Windows>,
+    split_screen: Res<SplitScreen>,
+    players: Query<(&Player, &Transform), Without<PlayerCamera>>,
+    mut cameras: Query<(&PlayerCamera, &mut Camera, &mut OrthographicProjection, &mut Transform)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let window_size = vec2(window.width(), window.height());
+    for (player_camera, mut camera, mut projection, mut transform) in cameras.iter_mut() {
+        if let Some((_player, car)) = players.iter().find(|(player, _car)| player.0 == player_camera.0) {
+            transform.translation.x = car.translation.x;
+            transform.translation.y = car.translation.y;
+        }
+        let (_offset, size) = viewport(player_camera.0, split_screen.players);
+        let half_size = window_size * size / 2f32;
+        projection.left = -half_size.x;
+        projection.right = half_size.x;
+        projection.bottom = -half_size.y;
+        projection.top = half_size.y;
+        camera.projection_matrix = projection.get_projection_matrix();
+    }
+}
+
+/// Runs a pass inside the viewport of its player.
+struct ViewportNode {
+    pass: PassNode<&'static MainPass>,
+    player: usize,
+}
+
+impl Node for ViewportNode {
+    fn input(&self) -> &[ResourceSlotInfo] {
+        self.pass.input()
+    }
+
+    fn prepare(&mut self, world: &mut World) {
+        self.pass.prepare(world);
+    }
+
+    fn update(
+        &mut self,
+        world: &World,
+        render_context: &mut dyn RenderContext,
+        input: &ResourceSlots,
+        output: &mut ResourceSlots,
+    ) {
+        let players = world.get_resource::<SplitScreen>().map(|split_screen| split_screen.players).unwrap_or(0);
+        let window = match world.get_resource::<Windows>().and_then(|windows| windows.get_primary()) {
+            Some(window) => window,
+            None => return,
+        };
+        if self.player >= players {
+            return;
+        }
+        let window_size = vec2(window.physical_width() as f32, window.physical_height() as f32);
+        let (offset, size) = viewport(self.player, players);
+        let mut context = ViewportContext {
+            context: render_context,
+            offset: offset * window_size,
+            size: size * window_size,
+        };
+        self.pass.update(world, &mut context, input, output);
+    }
+}
+
+/// Sets the viewport of every pass begun through it.
+struct ViewportContext<'a> {
+    context: &'a mut dyn RenderContext,
+    offset: Vec2,
+    size: Vec2,
+}
+
+impl RenderContext for ViewportContext<'_> {
+    fn resources(&self) -> &dyn RenderResourceContext {
+        self.context.resources()
+    }
+
+    fn resources_mut(&mut self) -> &mut dyn RenderResourceContext {
+        self.context.resources_mut()
+    }
+
+    fn copy_buffer_to_buffer(
+        &mut self,
+        source_buffer: BufferId,
+        source_offset: u64,
+        destination_buffer: BufferId,
+        destination_offset: u64,
+        size: u64,
+    ) {
+        self.context.copy_buffer_to_buffer(
+            source_buffer, source_offset, destination_buffer, destination_offset, size);
+    }
+
+    fn copy_buffer_to_texture(
+        &mut self,
+        source_buffer: BufferId,
+        source_offset: u64,
+        source_bytes_per_row: u32,
+        destination_texture: TextureId,
+        destination_origin: [u32; 3],
+        destination_mip_level: u32,
+        size: Extent3d,
+    ) {
+        self.context.copy_buffer_to_texture(
+            source_buffer, source_offset, source_bytes_per_row,
+            destination_texture, destination_origin, destination_mip_level, size);
+    }
+
+    fn copy_texture_to_buffer(
+        &mut self,
+        source_texture: TextureId,
+        source_origin: [u32; 3],
+        source_mip_level: u32,
+        destination_buffer: BufferId,
+        destination_offset: u64,
+        destination_bytes_per_row: u32,
+        size: Extent3d,
+    ) {
+        self.context.copy_texture_to_buffer(
+            source_texture, source_origin, source_mip_level,
+            destination_buffer, destination_offset, destination_bytes_per_row, size);
+    }
+
+    fn copy_texture_to_texture(
+        &mut self,
+        source_texture: TextureId,
+        source_origin: [u32; 3],
+        source_mip_level: u32,
+        destination_texture: TextureId,
+        destination_origin: [u32; 3],
+        destination_mip_level: u32,
+        size: Extent3d,
+    ) {
+        self.context.copy_texture_to_texture(
+            source_texture, source_origin, source_mip_level,
+            destination_texture, destination_origin, destination_mip_level, size);
+    }
+
+    fn begin_pass(
+        &mut self,
+        pass_descriptor: &PassDescriptor,
+        render_resource_bindings: &RenderResourceBindings,
+        run_pass: &mut dyn FnMut(&mut dyn RenderPass),
+    ) {
+        let (offset, size) = (self.offset, self.size);
+        self.context.begin_pass(pass_descriptor, render_resource_bindings, &mut |render_pass| {
+            render_pass.set_viewport(offset.x, offset.y, size.x, size.y, 0f32, 1f32);
+            run_pass(render_pass);
+        });
+    }
+}
@@ -0,0 +1,872 @@
+use std::fmt;
+use bevy::{
+    app::{AppBuilder, EventReader, Plugin},
+    asset::{AddAsset, AssetEvent, AssetLoader, AssetServer, Assets, LoadContext, LoadState, LoadedAsset},
+    ecs::{
+        entity::Entity,
+        query::With,
+        system::{Commands, IntoSystem, Query, Res, ResMut},
+    },
+    log::{error, info, warn},
+    math::{vec2, vec3, Quat, Vec3},
+    reflect::TypeUuid,
+    render::{color::Color, mesh::Mesh, wireframe::Wireframe},
+    sprite::{entity::SpriteBundle, Sprite},
+    transform::components::Transform,
+    utils::BoxedFuture,
+};
+use anyhow::anyhow;
+use ron::error::Position;
+use serde::{Deserialize, Serialize};
+use crate::line::{self, LineBundle, Point};
+use crate::boundary::{OffRoad, RoadBoundary};
+use crate::collision::{Collider, Layers, Shape};
+use crate::resources::GameResources;
+use crate::respawn::RespawnPolicy;
+use crate::race::{Checkpoint, Gate};
+use crate::simulation::Simulated;
+use crate::road::{self, JoinStyle, Overlap, Pivot, SectionDescription, Taper, Width};
+use crate::generator::{GeneratorError, GeneratorSettings};
+use crate::spline::{self, Cubic};
+
+/// Track file layout. Widths cascade: a segment without a width uses
+/// the width of its section, a section without a width uses the track one.
+/// `left` and `right` override a single side of the road at the same level.
+#[derive(Debug, Clone, Serialize, Deserialize, TypeUuid)]
+#[uuid = "e270992b-33d4-423c-8691-8fec809281f8"]
+pub struct TrackDescription {
+    pub name: String,
+    #[serde(default)]
+    pub author: Option<String>,
+    pub width: f32,
+    #[serde(default)]
+    pub start: Option<TrackStart>,
+    /// Circuit returning to its start, procedural tracks are always closed.
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub join: JoinStyle,
+    #[serde(default)]
+    pub taper: Taper,
+    /// Largest distance between a spline and the road built along it.
+    #[serde(default = "default_spline_tolerance")]
+    pub spline_tolerance: f32,
+    #[serde(default)]
+    pub off_road: OffRoad,
+    #[serde(default)]
+    pub respawn: RespawnPolicy,
+    /// Fails validation when the road overlaps itself, it is only reported otherwise.
+    #[serde(default)]
+    pub reject_overlaps: bool,
+    /// Generates sections from a seed, `sections` have to be empty then.
+    #[serde(default)]
+    pub procedural: Option<GeneratorSettings>,
+    #[serde(default)]
+    pub sections: Vec<TrackSection>,
+    #[serde(default)]
+    pub obstacles: Vec<TrackObstacle>,
+}
+
+fn default_spline_tolerance() -> f32 {
+    1f32
+}
+
+/// Where the road begins, it starts at the origin along the first segment by default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackStart {
+    pub position: (f32, f32),
+    pub direction: (f32, f32),
+    /// Width of the road before the first segment, the first segment one by default.
+    #[serde(default)]
+    pub width: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackSection {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub width: Option<f32>,
+    #[serde(default)]
+    pub left: Option<f32>,
+    #[serde(default)]
+    pub right: Option<f32>,
+    #[serde(default)]
+    pub segments: Vec<TrackSegment>,
+    /// Alternative to `segments`, a section is described by one of them.
+    #[serde(default)]
+    pub spline: Option<TrackSpline>,
+}
+
+pub type TrackPoint = (f32, f32);
+
+/// Static collider like a barrier or a bollard, in the coordinates of the road.
+/// The shape is turned by `angle` radians around `position`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackObstacle {
+    pub position: TrackPoint,
+    #[serde(default)]
+    pub angle: f32,
+    pub shape: Shape,
+}
+
+impl TrackObstacle {
+    pub fn transform(&self) -> Transform {
+        Transform {
+            translation: to_vec3(self.position),
+            rotation: Quat::from_rotation_z(self.angle),
+            ..Default::default()
+        }
+    }
+}
+
+/// Spline points are relative to the start of the section.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TrackSpline {
+    /// Curve passing through every point.
+    CatmullRom { points: Vec<TrackPoint> },
+    /// Chain of cubic curves given as `(control, control, end)`.
+    Bezier { curves: Vec<(TrackPoint, TrackPoint, TrackPoint)> },
+}
+
+impl TrackSpline {
+    fn ends(&self) -> Vec<TrackPoint> {
+        match self {
+            TrackSpline::CatmullRom { points } => points.clone(),
+            TrackSpline::Bezier { curves } => curves.iter().map(|(_a, _b, end)| *end).collect(),
+        }
+    }
+
+    fn curves(&self, incoming: Option<Vec3>) -> Vec<Cubic> {
+        match self {
+            TrackSpline::CatmullRom { points } => {
+                let points: Vec<Vec3> = std::iter::once(Vec3::ZERO)
+                    .chain(points.iter().map(|point| to_vec3(*point)))
+                    .collect();
+                spline::catmull_rom_chain(&points, incoming)
+            },
+            TrackSpline::Bezier { curves } => {
+                let mut start = Vec3::ZERO;
+                curves.iter()
+                    .map(|(control_a, control_b, end)| {
+                        let curve = Cubic::bezier(
+                            start, to_vec3(*control_a), to_vec3(*control_b), to_vec3(*end));
+                        start = curve.end;
+                        curve
+                    })
+                    .collect()
+            },
+        }
+    }
+}
+
+fn to_vec3((x, y): TrackPoint) -> Vec3 {
+    vec3(x, y, 0f32)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackSegment {
+    #[serde(default)]
+    pub width: Option<f32>,
+    #[serde(default)]
+    pub left: Option<f32>,
+    #[serde(default)]
+    pub right: Option<f32>,
+    pub shift: (f32, f32),
+}
+
+#[derive(Debug)]
+pub enum TrackError {
+    /// Always has the line and column of the error.
+    Parse(ron::Error),
+    NoSections,
+    ZeroStartDirection,
+    ProceduralWithSections,
+    Generator(GeneratorError),
+    Overlap(Overlap),
+    EmptySection { section: usize },
+    MixedSection { section: usize },
+    ZeroLengthShift { section: usize, segment: usize },
+    NegativeWidth { section: Option<usize>, segment: Option<usize>, width: f32 },
+    /// `Taper::Ease` without slices.
+    ZeroTaperSlices,
+    /// `JoinStyle::Round` without segments.
+    ZeroJoinSegments,
+    InvalidObstacle { obstacle: usize, reason: &'static str },
+}
+
+impl fmt::Display for TrackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackError::Parse(error) => write!(f, "Failed to parse track at {}", error),
+            TrackError::NoSections => write!(f, "Track has no sections"),
+            TrackError::ZeroStartDirection => write!(f, "Track start has zero direction"),
+            TrackError::ProceduralWithSections =>
+                write!(f, "Procedural track can not have sections"),
+            TrackError::Generator(error) => write!(f, "{}", error),
+            TrackError::Overlap(overlap) => write!(f, "Road overlaps itself: {}", overlap),
+            TrackError::EmptySection { section } =>
+                write!(f, "Section {} has no segments", section),
+            TrackError::MixedSection { section } =>
+                write!(f, "Section {} has both segments and spline", section),
+            TrackError::ZeroLengthShift { section, segment } =>
+                write!(f, "Section {} segment {} has zero length shift", section, segment),
+            TrackError::NegativeWidth { section, segment, width } => {
+                write!(f, "Negative width {}", width)?;
+                if let Some(section) = section {
+                    write!(f, " in section {}", section)?;
+                }
+                if let Some(segment) = segment {
+                    write!(f, " segment {}", segment)?;
+                }
+                Ok(())
+            },
+            TrackError::ZeroTaperSlices => write!(f, "Eased taper needs at least one slice"),
+            TrackError::ZeroJoinSegments => write!(f, "Round join needs at least one segment"),
+            TrackError::InvalidObstacle { obstacle, reason } =>
+                write!(f, "Obstacle {} is invalid: {}", obstacle, reason),
+        }
+    }
+}
+
+impl std::error::Error for TrackError {}
+
+impl From<ron::Error> for TrackError {
+    fn from(error: ron::Error) -> Self {
+        TrackError::Parse(error)
+    }
+}
+
+impl From<GeneratorError> for TrackError {
+    fn from(error: GeneratorError) -> Self {
+        TrackError::Generator(error)
+    }
+}
+
+impl TrackDescription {
+    pub fn from_ron(source: &str) -> Result<Self, TrackError> {
+        let track: TrackDescription = parse_ron(source)?;
+        track.validate()?;
+        Ok(track)
+    }
+
+    /// Road used when the track file can not be loaded.
+    pub fn builtin() -> Self {
+        let (x, y) = (50f32, 50f32);
+        let section = |shifts: &[(f32, f32)]| TrackSection {
+            name: None,
+            width: None,
+            left: None,
+            right: None,
+            segments: shifts.iter()
+                .map(|shift| TrackSegment { width: None, left: None, right: None, shift: *shift })
+                .collect(),
+            spline: None,
+        };
+        TrackDescription {
+            name: "Built-in".to_string(),
+            author: None,
+            width: 30f32,
+            start: None,
+            closed: false,
+            join: JoinStyle::Round { segments: 8 },
+            taper: Taper::default(),
+            spline_tolerance: default_spline_tolerance(),
+            off_road: OffRoad::default(),
+            respawn: RespawnPolicy::default(),
+            reject_overlaps: false,
+            procedural: None,
+            obstacles: Vec::new(),
+            sections: vec![
+                section(&[(x, 0.0)]),
+                section(&[(x, 0.0)]),
+                section(&[(x, y), (x, 0.0), (x, -y), (0.0, -y), (0.0, -y)]),
+                section(&[
+                    (-x, -y), (-x, -y), (-x, -y), (0.0, -y), (x, 0.0), (x, 0.0), (x, y), (x, y), (x, y), (x, y),
+                    (0.0, y), (0.0, y), (0.0, y), (0.0, y), (0.0, y), (-x, 0.0), (-x, 0.0), (-x, y), (-x, 0.0),
+                    (-x, 0.0), (-x, 0.0), (-x, 0.0), (-x, 0.0), (-x, 0.0), (-x, -y), (0.0, -y), (0.0, -y),
+                    (x, -y), (x, 0.0),
+                ]),
+            ],
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), TrackError> {
+        self.validate_description()?;
+        if self.reject_overlaps {
+            let sections = self.to_sections()?;
+            let start = self.start_pivot(&sections).position();
+            if let Some(overlap) = road::find_overlaps(start, &sections).first() {
+                return Err(TrackError::Overlap(*overlap));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_description(&self) -> Result<(), TrackError> {
+        check_width(Some(self.width), None, None)?;
+        if let Taper::Ease { slices: 0 } = self.taper {
+            return Err(TrackError::ZeroTaperSlices);
+        }
+        if let JoinStyle::Round { segments: 0 } = self.join {
+            return Err(TrackError::ZeroJoinSegments);
+        }
+        for (index, obstacle) in self.obstacles.iter().enumerate() {
+            obstacle.shape.check().map_err(|reason| TrackError::InvalidObstacle { obstacle: index, reason })?;
+        }
+        if let Some(start) = &self.start {
+            check_width(start.width, None, None)?;
+            if start.direction == (0f32, 0f32) {
+                return Err(TrackError::ZeroStartDirection);
+            }
+        }
+        if let Some(procedural) = &self.procedural {
+            if !self.sections.is_empty() {
+                return Err(TrackError::ProceduralWithSections);
+            }
+            procedural.generate()?;
+            return Ok(());
+        }
+        if self.sections.is_empty() {
+            return Err(TrackError::NoSections);
+        }
+        for (section_index, section) in self.sections.iter().enumerate() {
+            for width in [section.width, section.left, section.right] {
+                check_width(width, Some(section_index), None)?;
+            }
+            if let Some(spline) = &section.spline {
+                if !section.segments.is_empty() {
+                    return Err(TrackError::MixedSection { section: section_index });
+                }
+                let ends = spline.ends();
+                if ends.is_empty() {
+                    return Err(TrackError::EmptySection { section: section_index });
+                }
+                let starts = std::iter::once((0f32, 0f32)).chain(ends.iter().copied());
+                for (point_index, (start, end)) in starts.zip(ends.iter()).enumerate() {
+                    if start == *end {
+                        return Err(TrackError::ZeroLengthShift {
+                            section: section_index,
+                            segment: point_index,
+                        });
+                    }
+                }
+                continue;
+            }
+            if section.segments.is_empty() {
+                return Err(TrackError::EmptySection { section: section_index });
+            }
+            for (segment_index, segment) in section.segments.iter().enumerate() {
+                for width in [segment.width, segment.left, segment.right] {
+                    check_width(width, Some(section_index), Some(segment_index))?;
+                }
+                if segment.shift == (0f32, 0f32) {
+                    return Err(TrackError::ZeroLengthShift {
+                        section: section_index,
+                        segment: segment_index,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Authored tracks opt in, procedural ones are always closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed || self.procedural.is_some()
+    }
+
+    /// Sections of a closed track end at its start.
+    pub fn to_sections(&self) -> Result<Vec<SectionDescription>, TrackError> {
+        let mut sections = match &self.procedural {
+            Some(procedural) => procedural.generate()?,
+            None => self.authored_sections(),
+        };
+        if self.is_closed() {
+            road::close_loop(self.start_position(), &mut sections);
+        }
+        Ok(sections)
+    }
+
+    /// Splines are sampled into straight shifts, keeping the tangent
+    /// of the previous section so the road does not break at the seam.
+    fn authored_sections(&self) -> Vec<SectionDescription> {
+        let mut incoming = None;
+        self.sections.iter()
+            .map(|section| {
+                let section_width = resolve_width(
+                    section.width, section.left, section.right, Width::symmetric(self.width));
+                let description: SectionDescription = match &section.spline {
+                    Some(spline) => {
+                        let points = spline::sample(&spline.curves(incoming), self.spline_tolerance);
+                        let mut start = Vec3::ZERO;
+                        points.into_iter()
+                            .map(|point| {
+                                let shift = point - start;
+                                start = point;
+                                (section_width, shift)
+                            })
+                            .filter(|(_width, shift)| shift.length() > f32::EPSILON)
+                            .collect()
+                    },
+                    None => section.segments.iter()
+                        .map(|segment| {
+                            let width = resolve_width(
+                                segment.width, segment.left, segment.right, section_width);
+                            (width, to_vec3(segment.shift))
+                        })
+                        .collect(),
+                };
+                if let Some((_width, shift)) = description.last() {
+                    incoming = Some(*shift);
+                }
+                description
+            })
+            .collect()
+    }
+
+    pub fn start_position(&self) -> Vec3 {
+        self.start.as_ref()
+            .map(|start| to_vec3(start.position))
+            .unwrap_or(Vec3::ZERO)
+    }
+
+    pub fn start_pivot(&self, sections: &[SectionDescription]) -> Pivot {
+        let pivot = Pivot::start_of(sections);
+        match &self.start {
+            Some(start) => {
+                let width = match start.width {
+                    Some(width) => Width::symmetric(width),
+                    None => sections.iter()
+                        .flatten()
+                        .next()
+                        .map(|(width, _shift)| *width)
+                        .unwrap_or_else(|| Width::symmetric(self.width)),
+                };
+                Pivot::new(to_vec3(start.position), to_vec3(start.direction), width)
+            },
+            None => pivot,
+        }
+    }
+}
+
+/// Errors raised by serde, like missing fields, have no position in `ron`,
+/// they get the one where the parser stopped.
+fn parse_ron<'a, T: Deserialize<'a>>(source: &'a str) -> Result<T, ron::Error> {
+    let mut deserializer = ron::de::Deserializer::from_str(source)?;
+    let result = T::deserialize(&mut deserializer).and_then(|value| deserializer.end().map(|_| value));
+    result.map_err(|mut error| {
+        if error.position == (Position { line: 0, col: 0 }) {
+            let parsed = &source[..source.len() - deserializer.remainder().len()];
+            let line_start = parsed.rfind('\n').map(|newline| newline + 1).unwrap_or(0);
+            error.position = Position { line: parsed.matches('\n').count() + 1, col: parsed.len() - line_start + 1 };
+        }
+        error
+    })
+}
+
+fn resolve_width(width: Option<f32>, left: Option<f32>, right: Option<f32>, fallback: Width) -> Width {
+    let width = width.map(Width::symmetric).unwrap_or(fallback);
+    Width {
+        left: left.unwrap_or(width.left),
+        right: right.unwrap_or(width.right),
+    }
+}
+
+fn check_width(width: Option<f32>, section: Option<usize>, segment: Option<usize>) -> Result<(), TrackError> {
+    match width {
+        Some(width) if width < 0f32 => Err(TrackError::NegativeWidth { section, segment, width }),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Default)]
+pub struct TrackLoader;
+
+impl AssetLoader for TrackLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let source = std::str::from_utf8(bytes)?;
+            let track = TrackDescription::from_ron(source)
+                .map_err(|error| anyhow!("{}: {}", load_context.path().display(), error))?;
+            load_context.set_default_asset(LoadedAsset::new(track));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["track.ron"]
+    }
+}
+
+/// Marks every entity spawned from a track, so they can be replaced on reload.
+pub struct TrackEntity;
+
+/// Line across the start of the road, a lap starts by crossing it.
+pub struct StartFinishLine {
+    pub gate: Gate,
+}
+
+/// Middle of the road in world coordinates, it lives next to `RoadMesh`.
+/// `widths` has the width of the road at every point, a closed line ends at its start.
+pub struct Centerline {
+    pub points: Vec<Vec3>,
+    pub widths: Vec<Width>,
+    pub closed: bool,
+}
+
+impl Centerline {
+    /// Length of the line, around the loop for a closed one.
+    pub fn length(&self) -> f32 {
+        self.points.windows(2).map(|pair| pair[0].distance(pair[1])).sum()
+    }
+
+    /// Distance along the line to its closest point.
+    pub fn distance_along(&self, point: Vec3) -> Option<f32> {
+        let mut start = 0f32;
+        let mut closest: Option<(f32, f32)> = None;
+        for pair in self.points.windows(2) {
+            let along = pair[1] - pair[0];
+            let length = along.length();
+            if length > f32::EPSILON {
+                let t = ((point - pair[0]).dot(along) / along.length_squared()).clamp(0f32, 1f32);
+                let gap = (pair[0] + along * t - point).length_squared();
+                match closest {
+                    Some((closest_gap, _distance)) if closest_gap <= gap => {},
+                    _ => closest = Some((gap, start + length * t)),
+                }
+            }
+            start += length;
+        }
+        closest.map(|(_gap, distance)| distance)
+    }
+
+    /// Point, direction and width of the road `distance` along the line.
+    /// A closed line wraps around, an open one has nothing past its ends.
+    pub fn at(&self, distance: f32) -> Option<(Vec3, Vec3, Width)> {
+        let length = self.length();
+        let distance = if self.closed && length > 0f32 { distance.rem_euclid(length) } else { distance };
+        if !(0f32..=length).contains(&distance) {
+            return None;
+        }
+        let mut start = 0f32;
+        for (index, pair) in self.points.windows(2).enumerate() {
+            let along = pair[1] - pair[0];
+            let segment = along.length();
+            if segment > f32::EPSILON && distance <= start + segment {
+                let t = (distance - start) / segment;
+                let width = self.widths.get(index)?.lerp(*self.widths.get(index + 1)?, t);
+                return Some((pair[0] + along * t, along / segment, width));
+            }
+            start += segment;
+        }
+        None
+    }
+}
+
+/// The road is moved aside, so it does not cover the debug lines drawn at the origin.
+pub fn road_transform() -> Transform {
+    Transform::from_translation(vec3(-600.0, 000.0, 0.0))
+}
+
+pub struct TrackPlugin;
+
+impl Plugin for TrackPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_asset::<TrackDescription>()
+            .init_asset_loader::<TrackLoader>()
+            .add_system(track_events.system())
+            .add_system(track_fallback.system());
+        // only assets loaded after that are watched, so it has to come before `GameResources`.
+        let asset_server = app.world().get_resource::<AssetServer>().expect("AssetServer not accessible");
+        if let Err(error) = asset_server.watch_for_changes() {
+            error!("Track hot-reloading is disabled: {:?}", error);
+        }
+    }
+}
+
+fn track_events(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<TrackDescription>>,
+    tracks: Res<Assets<TrackDescription>>,
+    game_resources: Res<GameResources>,
+    track_entities: Query<Entity, With<TrackEntity>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    for event in events.iter() {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { .. } => continue,
+        };
+        if *handle != game_resources.track {
+            continue;
+        }
+        let track = match tracks.get(handle) {
+            Some(track) => track,
+            None => continue,
+        };
+        // the old road stays when the new one can not be built.
+        let sections = match track.to_sections() {
+            Ok(sections) => sections,
+            Err(error) => {
+                error!("Track {:?} can not be built: {}", track.name, error);
+                continue;
+            },
+        };
+        info!("Building track {:?}", track.name);
+        for entity in track_entities.iter() {
+            commands.entity(entity).despawn();
+        }
+        spawn_track(&mut commands, track, &sections, &game_resources, &mut meshes);
+    }
+}
+
+/// Builds the built-in track while there is no road, the file one replaces it once it loads.
+fn track_fallback(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    game_resources: Res<GameResources>,
+    track_entities: Query<Entity, With<TrackEntity>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    if asset_server.get_load_state(&game_resources.track) != LoadState::Failed || track_entities.iter().next().is_some() {
+        return;
+    }
+    warn!("Track could not be loaded. Falling back to the built-in track.");
+    let track = TrackDescription::builtin();
+    match track.to_sections() {
+        Ok(sections) => spawn_track(&mut commands, &track, &sections, &game_resources, &mut meshes),
+        Err(error) => error!("Built-in track can not be built: {}", error),
+    }
+}
+
+fn spawn_track(
+    commands: &mut Commands,
+    track: &TrackDescription,
+    sections: &[SectionDescription],
+    game_resources: &GameResources,
+    meshes: &mut Assets<Mesh>,
+) {
+    let start = track.start_pivot(sections);
+    let sections_line = road::sections_into_line(start.position(), sections);
+    for overlap in road::find_overlaps(start.position(), sections) {
+        warn!("Track {:?} overlaps itself: {}", track.name, overlap);
+    }
+    let sections_normals = line::line_to_normals(&line::line_to_points(&sections_line));
+    let (road, pivots) = road::generate_road(start, sections, track.join, track.taper, track.is_closed());
+    let road_mesh = road.to_mesh();
+    commands.insert_resource(track.respawn);
+    let transform = road_transform();
+    let centerline = Centerline {
+        points: sections_line.iter().map(|point| transform.mul_vec3(*point)).collect(),
+        widths: std::iter::once(start.width())
+            .chain(sections.iter().flatten().map(|(width, _shift)| *width))
+            .collect(),
+        closed: track.is_closed(),
+    };
+    let polylines: Vec<Vec<Vec3>> = road.boundary_polylines().iter()
+        .map(|polyline| polyline.iter().map(|point| transform.mul_vec3(*point)).collect())
+        .collect();
+    let boundary = RoadBoundary::new(&polylines, track.off_road);
+    commands
+        .spawn_bundle(LineBundle::from_line(sections_line, Color::BLUE))
+        .insert(TrackEntity);
+    commands
+        .spawn_bundle(LineBundle::from_points(sections_normals, Color::RED))
+        .insert(TrackEntity);
+    commands
+        .spawn_bundle(LineBundle::from_mesh(&road_mesh))
+        .insert(TrackEntity);
+    let outline = road.boundary_edges().iter()
+        .map(|[from, to]| Point(road.positions[*from as usize], road.positions[*to as usize]))
+        .collect();
+    commands
+        .spawn_bundle(LineBundle::from_points(outline, Color::YELLOW))
+        .insert(TrackEntity);
+    commands
+        .spawn_bundle(SpriteBundle {
+            mesh: meshes.add(road_mesh),
+            material: game_resources.road.clone(),
+            sprite: Sprite::new(vec2(1.0, 1.0)),
+            transform,
+            ..Default::default()
+        })
+        .insert(Wireframe)
+        .insert(road)
+        .insert(boundary)
+        .insert(centerline)
+        .insert(TrackEntity);
+    // the start pivot has the width and direction the road begins with, whatever its first segment is.
+    let gate = Gate::at_pivot(&transform, &start);
+    commands
+        .spawn_bundle(LineBundle::from_points(vec![Point(gate.left, gate.right)], Color::WHITE))
+        .insert(StartFinishLine { gate })
+        .insert(TrackEntity);
+    for obstacle in track.obstacles.iter() {
+        let obstacle_transform = transform.mul_transform(obstacle.transform());
+        let outline = obstacle.shape.outline().iter()
+            .map(|point| obstacle_transform.mul_vec3(point.extend(0f32)))
+            .collect();
+        commands
+            .spawn_bundle(LineBundle::from_line(outline, Color::ORANGE))
+            .insert(Simulated::new(obstacle_transform))
+            .insert(Collider::solid(obstacle.shape.clone(), Layers::WALLS))
+            .insert(TrackEntity);
+    }
+    // the last pivot is the finish, it matches the start line of a closed track.
+    for (index, pivot) in pivots.iter().enumerate() {
+        let gate = Gate::at_pivot(&transform, pivot);
+        commands
+            .spawn_bundle(LineBundle::from_points(vec![Point(gate.left, gate.right)], Color::ORANGE_RED))
+            .insert(Checkpoint { index, gate })
+            .insert(TrackEntity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRAIGHT: &str = "(segments: [(shift: (50.0, 0.0))])";
+
+    fn track(fields: &str) -> Result<TrackDescription, TrackError> {
+        TrackDescription::from_ron(&format!("(\n    name: \"Test\",\n    width: 30.0,\n    {}\n)", fields))
+    }
+
+    #[test]
+    fn builtin_track_is_valid() {
+        assert!(TrackDescription::builtin().validate().is_ok());
+    }
+
+    #[test]
+    fn parse_errors_have_a_position() {
+        let syntax = track("sections: [(segments: [(shift: (50.0 0.0))])],");
+        match syntax {
+            Err(TrackError::Parse(error)) => assert_eq!(error.position, Position { line: 4, col: 42 }),
+            other => panic!("{:?}", other),
+        }
+        // serde only knows the field is missing once the track is over.
+        let missing = TrackDescription::from_ron("(\n    name: \"Test\",\n    sections: [],\n)");
+        match missing {
+            Err(TrackError::Parse(error)) => {
+                assert_eq!(error.position.line, 4);
+                assert!(error.to_string().contains("width"), "{}", error);
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn validation_errors_name_the_place() {
+        let sections = |sections: &str| track(&format!("sections: [{}],", sections));
+        assert!(matches!(track(""), Err(TrackError::NoSections)));
+        assert!(matches!(
+            track(&format!("taper: Ease(slices: 0), sections: [{}],", STRAIGHT)),
+            Err(TrackError::ZeroTaperSlices)));
+        assert!(matches!(
+            track(&format!("join: Round(segments: 0), sections: [{}],", STRAIGHT)),
+            Err(TrackError::ZeroJoinSegments)));
+        let start = "start: Some((position: (0.0, 0.0), direction: (0.0, 0.0))),";
+        assert!(matches!(
+            track(&format!("{} sections: [{}],", start, STRAIGHT)),
+            Err(TrackError::ZeroStartDirection)));
+        assert!(matches!(
+            sections(&format!("{}, (segments: [])", STRAIGHT)),
+            Err(TrackError::EmptySection { section: 1 })));
+        assert!(matches!(
+            sections("(spline: Some(CatmullRom(points: [])))"),
+            Err(TrackError::EmptySection { section: 0 })));
+        assert!(matches!(
+            sections("(segments: [(shift: (50.0, 0.0))], spline: Some(CatmullRom(points: [(50.0, 0.0)])))"),
+            Err(TrackError::MixedSection { section: 0 })));
+        assert!(matches!(
+            sections(&format!("{}, (segments: [(shift: (50.0, 0.0)), (shift: (0.0, 0.0))])", STRAIGHT)),
+            Err(TrackError::ZeroLengthShift { section: 1, segment: 1 })));
+        assert!(matches!(
+            sections("(spline: Some(CatmullRom(points: [(50.0, 0.0), (50.0, 0.0)])))"),
+            Err(TrackError::ZeroLengthShift { section: 0, segment: 1 })));
+        let procedural = "procedural: Some((\
+            seed: 1, length: 2000.0, turn_density: 3.0, min_corner_radius: 40.0, width: (20.0, 30.0))),";
+        assert!(matches!(
+            track(&format!("{} sections: [{}],", procedural, STRAIGHT)),
+            Err(TrackError::ProceduralWithSections)));
+    }
+
+    #[test]
+    fn track_assets_are_valid() {
+        let spline = TrackDescription::from_ron(include_str!("../assets/tracks/spline.track.ron")).unwrap();
+        assert_eq!(spline.obstacles.len(), 4);
+        TrackDescription::from_ron(include_str!("../assets/tracks/default.track.ron")).unwrap();
+        TrackDescription::from_ron(include_str!("../assets/tracks/random.track.ron")).unwrap();
+    }
+
+    #[test]
+    fn obstacles_are_checked() {
+        let obstacles = |obstacles: &str| track(&format!("sections: [{}], obstacles: [{}],", STRAIGHT, obstacles));
+        let reason = |list: &str| match obstacles(list) {
+            Err(TrackError::InvalidObstacle { obstacle, reason }) => (obstacle, reason),
+            other => panic!("{:?}", other),
+        };
+        let circle = "(position: (0.0, 50.0), shape: Circle(radius: 5.0))";
+        assert!(obstacles(circle).is_ok());
+        assert_eq!(reason(&format!("{}, (position: (0.0, 0.0), shape: Box(size: (0.0, 5.0)))", circle)).0, 1);
+        assert_eq!(reason("(position: (0.0, 0.0), shape: Polyline(points: [(1.0, 1.0)]))").0, 0);
+        let dent = "ConvexPolygon(points: [(0.0, 0.0), (10.0, 0.0), (5.0, 2.0), (10.0, 10.0), (0.0, 10.0)])";
+        assert_eq!(reason(&format!("(position: (0.0, 0.0), shape: {})", dent)).1, "polygon must be convex");
+    }
+
+    #[test]
+    fn generator_errors_reach_the_track() {
+        let procedural = GeneratorSettings {
+            seed: 1,
+            length: 2000f32,
+            turn_density: 3f32,
+            min_corner_radius: 100_000f32,
+            width: (20f32, 30f32),
+            attempts: 2,
+        };
+        let track = TrackDescription { procedural: Some(procedural), sections: Vec::new(), ..TrackDescription::builtin() };
+        let exhausted = |result| matches!(result, Err(TrackError::Generator(GeneratorError::Exhausted { attempts: 2 })));
+        assert!(exhausted(track.validate()));
+        assert!(exhausted(track.to_sections().map(|_| ())));
+    }
+
+    #[test]
+    fn negative_widths_are_rejected_where_they_are() {
+        let negative = |fields: &str| match track(fields) {
+            Err(TrackError::NegativeWidth { section, segment, width }) => (section, segment, width),
+            other => panic!("{:?}", other),
+        };
+        let start = "start: Some((position: (0.0, 0.0), direction: (1.0, 0.0), width: Some(-1.0))),";
+        assert_eq!(negative(&format!("{} sections: [{}],", start, STRAIGHT)), (None, None, -1f32));
+        let section = "(left: Some(-2.0), segments: [(shift: (50.0, 0.0))])";
+        assert_eq!(negative(&format!("sections: [{}, {}],", STRAIGHT, section)), (Some(1), None, -2f32));
+        let segment = "(segments: [(shift: (50.0, 0.0)), (right: Some(-3.0), shift: (50.0, 0.0))])";
+        assert_eq!(negative(&format!("sections: [{}],", segment)), (Some(0), Some(1), -3f32));
+    }
+
+    #[test]
+    fn centerlines_are_walked_by_distance() {
+        let (narrow, wide) = (Width::symmetric(10f32), Width::symmetric(30f32));
+        let points = vec![Vec3::ZERO, vec3(100f32, 0f32, 0f32), vec3(100f32, 100f32, 0f32)];
+        let open = Centerline { points, widths: vec![narrow, narrow, wide], closed: false };
+        assert_eq!(open.length(), 200f32);
+        assert_eq!(open.at(50f32), Some((vec3(50f32, 0f32, 0f32), Vec3::X, narrow)));
+        assert_eq!(open.at(150f32), Some((vec3(100f32, 50f32, 0f32), Vec3::Y, Width::symmetric(20f32))));
+        assert_eq!(open.at(-1f32), None);
+        assert_eq!(open.at(201f32), None);
+        assert_eq!(open.distance_along(vec3(120f32, 40f32, 0f32)), Some(140f32));
+
+        let mut points = open.points.clone();
+        points.push(Vec3::ZERO);
+        let closed = Centerline { points, widths: vec![narrow; 4], closed: true };
+        let position = |distance: f32| closed.at(distance).map(|(position, ..)| position);
+        assert_eq!(position(-50f32), position(closed.length() - 50f32));
+        assert!(position(1000f32).is_some());
+    }
+}
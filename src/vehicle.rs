@@ -0,0 +1,180 @@
+use bevy::{
+    app::{AppBuilder, Plugin},
+    ecs::{
+        bundle::Bundle,
+        schedule::SystemLabel,
+        system::{IntoSystem, Query},
+    },
+    math::{Quat, Vec2, Vec3},
+    prelude::ParallelSystemDescriptorCoercion,
+    transform::components::Transform,
+};
+use crate::damage::Health;
+use crate::simulation::{Simulated, SimulationStage, SimulationSystem, TIMESTEP};
+
+/// Slower cars without throttle are stopped, so they do not creep forever.
+pub const STOP_SPEED: f32 = 1f32;
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, SystemLabel)]
+pub enum VehiclePluginSystem {
+    Physics,
+}
+
+pub struct VehiclePlugin;
+
+impl Plugin for VehiclePlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app
+            .add_system_to_stage(
+                SimulationStage,
+                vehicle_physics.system()
+                    .label(VehiclePluginSystem::Physics)
+                    .label(SimulationSystem::Movement)
+                    .after(SimulationSystem::Begin));
+    }
+}
+
+/// Arcade car parameters, distances are in pixels. The car faces `+X` of its transform.
+#[derive(Debug, Clone)]
+pub struct Vehicle {
+    pub mass: f32,
+    pub engine_force: f32,
+    pub reverse_force: f32,
+    pub brake_force: f32,
+    /// Air resistance, grows with the square of speed.
+    pub drag: f32,
+    /// Resistance growing linearly with speed.
+    pub rolling_resistance: f32,
+    /// How fast sideways sliding is damped, per second.
+    pub grip: f32,
+    /// Grip while the handbrake is pulled, lower values drift more.
+    pub handbrake_grip: f32,
+    pub handbrake_force: f32,
+    /// Extra turning while the handbrake is pulled.
+    pub handbrake_turn: f32,
+    /// Largest wheel angle in radians, reached at standstill.
+    pub max_steer_angle: f32,
+    /// Speed at which the largest wheel angle is halved.
+    pub steer_falloff_speed: f32,
+    /// Distance between the axles.
+    pub wheelbase: f32,
+    /// Part of the speed into a wall the car bounces back with.
+    pub restitution: f32,
+    /// Part of the speed along a wall lost in a head-on impact.
+    pub impact_friction: f32,
+}
+
+impl Default for Vehicle {
+    fn default() -> Self {
+        Vehicle {
+            mass: 1000f32,
+            engine_force: 300_000f32,
+            reverse_force: 120_000f32,
+            brake_force: 600_000f32,
+            drag: 0.8f32,
+            rolling_resistance: 50f32,
+            grip: 8f32,
+            handbrake_grip: 1.5f32,
+            handbrake_force: 150_000f32,
+            handbrake_turn: 1.5f32,
+            max_steer_angle: 0.6f32,
+            steer_falloff_speed: 300f32,
+            wheelbase: 40f32,
+            restitution: 0.3f32,
+            impact_friction: 0.6f32,
+        }
+    }
+}
+
+/// What the driver does, values go from 0 to 1, `steer` goes from -1 (right) to 1 (left).
+/// Players, AI, replays or the network write it, only `vehicle_physics` reads it,
+/// so every driver moves cars the same way.
+#[derive(Debug, Clone, Default)]
+pub struct CarControls {
+    pub throttle: f32,
+    pub brake: f32,
+    pub steer: f32,
+    pub handbrake: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VehicleState {
+    pub velocity: Vec2,
+    pub angular_velocity: f32,
+    /// Wheel angle of the last step.
+    pub steer_angle: f32,
+}
+
+#[derive(Bundle, Default)]
+pub struct VehicleBundle {
+    pub vehicle: Vehicle,
+    pub controls: CarControls,
+    pub state: VehicleState,
+}
+
+impl Vehicle {
+    /// Wheel angle for the steering, fast cars can not turn their wheels that much.
+    pub fn steer_angle(&self, steer: f32, speed: f32) -> f32 {
+        steer.clamp(-1f32, 1f32) * self.max_steer_angle / (1f32 + speed / self.steer_falloff_speed)
+    }
+
+    /// Advances the car by `delta` seconds. Damaged cars have `condition` below 1,
+    /// they lose power and grip, a wreck at 0 only rolls.
+    pub fn step(
+        &self,
+        controls: &CarControls,
+        condition: f32,
+        state: &mut VehicleState,
+        transform: &mut Transform,
+        delta: f32,
+    ) {
+        // a wreck only rolls, whatever its driver still holds.
+        let idle = CarControls::default();
+        let controls = if condition > 0f32 { controls } else { &idle };
+        let forward = transform.rotation.mul_vec3(Vec3::X).truncate().normalize();
+        let side = forward.perp();
+        let forward_speed = state.velocity.dot(forward);
+        let side_speed = state.velocity.dot(side);
+        let throttle = controls.throttle.clamp(0f32, 1f32);
+        let brake = controls.brake.clamp(0f32, 1f32);
+
+        let mut traction = throttle * self.engine_force * condition;
+        if forward_speed > STOP_SPEED {
+            traction -= brake * self.brake_force;
+        } else {
+            traction -= brake * self.reverse_force * condition;
+        }
+        if controls.handbrake && forward_speed.abs() > STOP_SPEED {
+            traction -= forward_speed.signum() * self.handbrake_force;
+        }
+        let resistance = -state.velocity * (self.drag * state.velocity.length() + self.rolling_resistance);
+        let acceleration = (forward * traction + resistance) / self.mass;
+        let forward_speed = forward_speed + acceleration.dot(forward) * delta;
+        let side_speed = side_speed + acceleration.dot(side) * delta;
+
+        let grip = if controls.handbrake { self.handbrake_grip } else { self.grip };
+        let grip = grip * (1f32 + condition) / 2f32;
+        let side_speed = side_speed * (-grip * delta).exp();
+        state.velocity = forward * forward_speed + side * side_speed;
+        if throttle <= 0f32 && state.velocity.length() < STOP_SPEED {
+            state.velocity = Vec2::ZERO;
+        }
+
+        state.steer_angle = self.steer_angle(controls.steer, state.velocity.length());
+        state.angular_velocity = forward_speed * state.steer_angle.tan() / self.wheelbase;
+        if controls.handbrake {
+            state.angular_velocity *= self.handbrake_turn;
+        }
+        transform.rotation = (transform.rotation * Quat::from_rotation_z(state.angular_velocity * delta)).normalize();
+        transform.translation += state.velocity.extend(0f32) * delta;
+    }
+}
+
+fn vehicle_physics(
+    mut vehicles: Query<(&Vehicle, &CarControls, Option<&Health>, &mut VehicleState, &mut Simulated)>,
+) {
+    for (vehicle, controls, health, mut state, mut body) in vehicles.iter_mut() {
+        let condition = health.map(|health| health.condition()).unwrap_or(1f32);
+        vehicle.step(controls, condition, &mut state, &mut body.current, TIMESTEP as f32);
+    }
+}